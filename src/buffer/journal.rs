@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+/// One splice made to the buffer: `removed` lines starting at the
+/// zero-indexed line `start` were replaced by `inserted` lines.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Change {
+    pub start: usize,
+    pub removed: Vec<Vec<u8>>,
+    pub inserted: usize,
+}
+
+/// The changes made by one command, along with the current line and marks
+/// from before it ran.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct UndoRecord {
+    pub changes: Vec<Change>,
+    pub current_line: usize,
+    pub marks: HashMap<char, usize>,
+}
+
+/// Records every change to the buffer so the last command can be undone.
+///
+/// # Fields
+///
+/// * `pending` - Changes made by the command that is running, with the state from before it started.
+/// * `last` - The changes made by the last command that changed the buffer.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Journal {
+    pub pending: UndoRecord,
+    pub last: Option<UndoRecord>,
+}
+
+impl Journal {
+    /// Starts recording a new command.
+    pub fn begin(&mut self, current_line: usize, marks: &HashMap<char, usize>) {
+        self.pending = UndoRecord {
+            changes: vec![],
+            current_line,
+            marks: marks.clone(),
+        };
+    }
+
+    /// Records one splice made by the running command.
+    pub fn record(&mut self, change: Change) {
+        self.pending.changes.push(change);
+    }
+
+    /// Finishes recording. If the command changed the buffer, it becomes
+    /// the command that `u` undoes.
+    pub fn commit(&mut self) {
+        if !self.pending.changes.is_empty() {
+            self.last = Some(std::mem::take(&mut self.pending));
+        }
+    }
+}
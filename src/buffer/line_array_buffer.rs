@@ -1,8 +1,73 @@
+use crate::buffer::journal::{Change, Journal};
+use crate::command_structs::Substitution;
+use std::collections::{HashMap, VecDeque};
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct LineBuffer {
-    pub lines: Option<Vec<String>>,
+    pub lines: Option<Vec<Vec<u8>>>,
     pub filename: Option<String>,
     pub current_line: usize,
+    pub last_pattern: Option<Vec<u8>>,
+    pub marks: HashMap<char, usize>,
+    pub last_substitution: Option<Substitution>,
+    pub global_marks: VecDeque<usize>,
+    pub global_active: bool,
+    pub journal: Journal,
+    pub cut_buffer: Vec<Vec<u8>>,
+    pub modified: bool,
+    pub modified_warned: bool,
+    pub window_lines: usize,
+    pub last_error: Option<String>,
+    pub explain_errors: bool,
+    pub last_shell_command: Option<String>,
+    pub script_mode: bool,
+    pub restricted: bool,
+    pub line_ending: LineEnding,
+    pub strip_trailing_cr: bool,
+    pub missing_newline: bool,
+    pub keep_missing_newline: bool,
+}
+
+/// The line ending written after each line when the buffer is saved.
+///
+/// # Values
+///
+/// * `Lf` - Lines end with `\n`.
+/// * `CrLf` - Lines end with `\r\n`, as in files from Windows.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Returns the characters that end a line.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// The number of lines `z` prints when the terminal height is unknown.
+pub const DEFAULT_WINDOW_LINES: usize = 22;
+
+/// Moves a one-indexed line number to account for the zero-indexed lines
+/// `start..end` being replaced by `inserted` lines.
+///
+/// Returns `false` if the line itself was removed.
+fn shift_line(line: &mut usize, start: usize, end: usize, inserted: usize) -> bool {
+    let index = *line - 1;
+    if index < start {
+        true
+    } else if index >= end {
+        *line = *line + inserted - (end - start);
+        true
+    } else {
+        false
+    }
 }
 
 impl LineBuffer {
@@ -18,24 +83,124 @@ impl LineBuffer {
             filename: None,
             lines: None,
             current_line: 0,
+            last_pattern: None,
+            marks: HashMap::new(),
+            last_substitution: None,
+            global_marks: VecDeque::new(),
+            global_active: false,
+            journal: Journal::default(),
+            cut_buffer: vec![],
+            modified: false,
+            modified_warned: false,
+            window_lines: DEFAULT_WINDOW_LINES,
+            last_error: None,
+            explain_errors: false,
+            last_shell_command: None,
+            script_mode: false,
+            restricted: false,
+            line_ending: LineEnding::Lf,
+            strip_trailing_cr: false,
+            missing_newline: false,
+            keep_missing_newline: false,
+        }
+    }
+
+    /// Replaces the text of the zero-indexed line `index` in place, keeping
+    /// any marks on it.
+    pub fn set_line(&mut self, index: usize, line: Vec<u8>) {
+        if let Some(lines) = &mut self.lines {
+            let old = std::mem::replace(&mut lines[index], line);
+            self.modified = true;
+            self.journal.record(Change {
+                start: index,
+                removed: vec![old],
+                inserted: 1,
+            });
         }
     }
 
+    /// Replaces the zero-indexed lines `start..end` with `lines`, returning
+    /// the lines that were removed.
+    ///
+    /// Marks and lines queued by a global command move with their lines when
+    /// they are after the replaced range, and are dropped when their line is
+    /// removed. The range is clamped to the buffer.
+    pub fn splice_lines(&mut self, start: usize, end: usize, lines: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let buffer_lines = self.lines.get_or_insert_with(Vec::new);
+        let end = end.min(buffer_lines.len());
+        let start = start.min(end);
+        let inserted = lines.len();
+        let removed: Vec<Vec<u8>> = buffer_lines.splice(start..end, lines).collect();
+
+        self.marks
+            .retain(|_, line| shift_line(line, start, end, inserted));
+        self.global_marks
+            .retain_mut(|line| shift_line(line, start, end, inserted));
+        self.modified = true;
+        self.journal.record(Change {
+            start,
+            removed: removed.clone(),
+            inserted,
+        });
+        removed
+    }
+
+    /// Starts recording the changes made by a command so it can be undone.
+    pub fn begin_change(&mut self) {
+        self.journal.begin(self.current_line, &self.marks);
+    }
+
+    /// Finishes recording the changes made by a command.
+    pub fn end_change(&mut self) {
+        self.journal.commit();
+    }
+
+    /// Reverts the changes made by the last command that changed the buffer,
+    /// restoring the current line and marks from before it ran.
+    ///
+    /// The reverted changes are recorded like any other, so undoing again
+    /// redoes the command.
+    ///
+    /// Returns `false` if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        let record = match self.journal.last.take() {
+            Some(record) => record,
+            None => return false,
+        };
+        for change in record.changes.into_iter().rev() {
+            self.splice_lines(change.start, change.start + change.inserted, change.removed);
+        }
+        self.current_line = record.current_line;
+        self.marks = record.marks;
+        true
+    }
+
+    /// Replaces the whole buffer with `lines`, as when a new file is edited.
+    ///
+    /// Marks and the undo history are cleared, the buffer is marked as
+    /// unmodified, and the current line is set to the last line.
+    pub fn replace_contents(&mut self, lines: Vec<Vec<u8>>) {
+        self.current_line = lines.len();
+        self.lines = Some(lines);
+        self.marks.clear();
+        self.journal = Journal::default();
+        self.modified = false;
+    }
+
     // Constructor to create LineBuffer from a file
     pub fn from_file(filename: &str) -> Result<Self, std::io::Error> {
-        use std::fs::{metadata, File};
-        use std::io::{BufRead, BufReader};
+        use std::fs::metadata;
 
         match metadata(filename) {
             Ok(_) => {
-                let file = File::open(filename)?;
-                let reader = BufReader::new(file);
-                let lines: Vec<String> = reader.lines().collect::<Result<_, _>>()?;
+                let file = read_lines(filename, false)?;
 
                 Ok(LineBuffer {
                     filename: Some(filename.to_string()),
-                    lines: Some(lines),
-                    current_line: 0,
+                    lines: Some(file.lines),
+                    line_ending: file.line_ending,
+                    missing_newline: file.missing_newline,
+                    ..Self::empty()
                 })
             }
             Err(_) => Ok(LineBuffer {
@@ -45,8 +210,32 @@ impl LineBuffer {
         }
     }
 
-    // Save the lines to a file
-    pub fn save(&mut self, filename: Option<&str>) -> Result<(), std::io::Error> {
+    /// Removes a trailing carriage return from every line and switches to
+    /// `\n` line endings, as `--strip-trailing-cr` does for a file loaded at
+    /// startup. The buffer is not marked as modified.
+    pub fn strip_carriage_returns(&mut self) {
+        if let Some(lines) = &mut self.lines {
+            for line in lines.iter_mut().filter(|line| line.ends_with(b"\r")) {
+                line.pop();
+            }
+        }
+        self.line_ending = LineEnding::Lf;
+    }
+
+    /// Writes the zero-indexed lines `start..end` to a file, or appends them
+    /// to it when `append` is set. Each line ends with the buffer's line
+    /// ending, except a last line that is kept without a newline.
+    ///
+    /// A filename becomes the default filename; without one the default file
+    /// is written. Writing the whole buffer clears the modified flag, but
+    /// writing part of it or appending does not.
+    pub fn write_range(
+        &mut self,
+        filename: Option<&str>,
+        start: usize,
+        end: usize,
+        append: bool,
+    ) -> Result<(), std::io::Error> {
         use std::io::Write;
         let filename = match filename {
             Some(f) => {
@@ -64,43 +253,146 @@ impl LineBuffer {
             },
         };
 
-        let mut file = std::fs::File::create(&filename)?;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(&filename)?;
 
         if let Some(lines) = &self.lines {
-            for line in lines {
-                writeln!(file, "{}", line)?;
+            for (index, line) in lines.iter().enumerate().take(end).skip(start) {
+                file.write_all(line)?;
+                if !self.missing_newline || index + 1 < lines.len() {
+                    file.write_all(self.line_ending.as_str().as_bytes())?;
+                }
             }
         }
 
+        if !append && start == 0 && end == self.len() {
+            self.modified = false;
+        }
         Ok(())
     }
 
+    /// Counts the bytes in the buffer as they are written to a file.
     pub fn char_length(&self) -> Option<usize> {
-        if let Some(lines) = &self.lines {
-            let total_length = lines
-                .iter()
-                .map(|s| s.len())
-                .reduce(|acc, sl| acc + sl)
-                .unwrap_or(0);
-
-            // Adding the number of lines to the total length
-            Some(total_length + lines.len())
+        self.lines.as_ref().map(|_| self.range_byte_count(0, self.len()))
+    }
+
+    /// Counts the bytes in the zero-indexed lines `start..end` as they are
+    /// written to a file by `write_range`.
+    pub fn range_byte_count(&self, start: usize, end: usize) -> usize {
+        let lines = self.lines.as_deref().unwrap_or_default();
+        let count = byte_count(&lines[start..end], self.line_ending);
+        if self.missing_newline && start < end && end == lines.len() {
+            count - self.line_ending.as_str().len()
         } else {
-            None
+            count
+        }
+    }
+}
+
+/// Counts the bytes in `lines` as written to a file, with one line ending per line.
+pub fn byte_count(lines: &[Vec<u8>], line_ending: LineEnding) -> usize {
+    lines
+        .iter()
+        .map(|line| line.len() + line_ending.as_str().len())
+        .sum()
+}
+
+/// The lines of a file and how they were terminated.
+///
+/// # Fields
+///
+/// * `lines` - The lines, without their line endings.
+/// * `line_ending` - The line ending style of the file.
+/// * `missing_newline` - `true` if the last line had no line ending.
+/// * `byte_count` - The size of the file in bytes, before any carriage returns were stripped.
+#[derive(Debug, PartialEq, Eq)]
+pub struct FileLines {
+    pub lines: Vec<Vec<u8>>,
+    pub line_ending: LineEnding,
+    pub missing_newline: bool,
+    pub byte_count: usize,
+}
+
+/// Splits the contents of a file into lines and detects its line ending
+/// style. Lines are kept as raw bytes, so text that is not UTF-8, and NUL
+/// bytes, are loaded unchanged.
+///
+/// The text uses `\r\n` endings when every line ending in `\n` also ends in
+/// `\r`; those carriage returns are removed. Otherwise carriage returns are
+/// kept as part of the line, unless `strip_trailing_cr` is set, which removes
+/// a trailing carriage return from every line and always gives `\n` endings.
+pub fn split_lines(text: &[u8], strip_trailing_cr: bool) -> FileLines {
+    let mut lines: Vec<Vec<u8>> = text.split(|&byte| byte == b'\n').map(<[u8]>::to_vec).collect();
+    let missing_newline = !text.is_empty() && !text.ends_with(b"\n");
+    let terminated = if missing_newline {
+        lines.len() - 1
+    } else {
+        lines.pop();
+        lines.len()
+    };
+
+    let crlf = terminated > 0 && lines[..terminated].iter().all(|line| line.ends_with(b"\r"));
+    let stripped = match (strip_trailing_cr, crlf) {
+        (true, _) => lines.len(),
+        (false, true) => terminated,
+        (false, false) => 0,
+    };
+    for line in &mut lines[..stripped] {
+        if line.ends_with(b"\r") {
+            line.pop();
         }
     }
+
+    let line_ending = if crlf && !strip_trailing_cr {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    };
+    FileLines {
+        lines,
+        line_ending,
+        missing_newline,
+        byte_count: text.len(),
+    }
 }
 
+/// Reads the lines of a file, without their line endings, and detects its
+/// line ending style as described for `split_lines`.
+pub fn read_lines(filename: &str, strip_trailing_cr: bool) -> Result<FileLines, std::io::Error> {
+    let text = std::fs::read(filename)?;
+    Ok(split_lines(&text, strip_trailing_cr))
+}
+
+/// Converts text to buffer lines, for comparing with the buffer in tests.
+#[cfg(test)]
+pub fn text_lines(lines: &[&str]) -> Vec<Vec<u8>> {
+    lines.iter().map(|line| line.as_bytes().to_vec()).collect()
+}
+
+/// Parses and runs one command line with no input text, for tests.
 #[cfg(test)]
+pub(crate) fn run(
+    buffer: &mut LineBuffer,
+    line: &str,
+) -> Result<crate::ed_commands::REPLStatus, Box<dyn std::error::Error>> {
+    let command = crate::ed_command_parser::parse_line(line.as_bytes()).expect("bad line parse");
+    crate::ed_commands::command_runner(buffer, &command, &mut VecDeque::new())
+}
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
 
     #[test]
     fn test_empty_buffer_create() {
         let buff = LineBuffer::empty();
-        assert!(buff.lines == None);
-        assert!(buff.filename == None);
+        assert!(buff.lines.is_none());
+        assert!(buff.filename.is_none());
     }
 
     #[test]
@@ -110,8 +402,8 @@ mod tests {
         assert!(buff.filename == Some(filename.to_string()));
         assert!(buff.lines.is_some());
         if let Some(local_lines) = buff.lines {
-            assert!(local_lines[0] == "one".to_string());
-            assert!(local_lines[4] == "five".to_string());
+            assert!(local_lines[0] == b"one");
+            assert!(local_lines[4] == b"five");
         }
     }
 
@@ -121,7 +413,7 @@ mod tests {
         let mut buff = LineBuffer::from_file(filename).unwrap();
 
         let out_filename = "/tmp/out.txt";
-        buff.save(Some(out_filename)).unwrap();
+        buff.write_range(Some(out_filename), 0, buff.len(), false).unwrap();
 
         let saved_buff = LineBuffer::from_file(out_filename).unwrap();
 
@@ -131,6 +423,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_range_and_append() {
+        let filename = "test_files/one.txt";
+        let mut buff = LineBuffer::from_file(filename).unwrap();
+        buff.modified = true;
+
+        let out_filename = "/tmp/out_range.txt";
+        buff.write_range(Some(out_filename), 1, 3, false).unwrap();
+        assert!(buff.modified, "A partial write should keep the buffer modified.");
+        buff.write_range(Some(out_filename), 4, 5, true).unwrap();
+        assert!(buff.modified, "An append should keep the buffer modified.");
+
+        assert_eq!(
+            read_lines(out_filename, false).unwrap().lines,
+            text_lines(&["two", "three", "five"])
+        );
+
+        buff.write_range(Some(out_filename), 0, 5, false).unwrap();
+        assert!(!buff.modified, "A whole-buffer write should clear the modified flag.");
+    }
+
+    #[test]
+    fn test_splice_moves_marks() {
+        let filename = "test_files/one.txt";
+        let mut buff = LineBuffer::from_file(filename).unwrap();
+        buff.marks.insert('a', 1);
+        buff.marks.insert('b', 3);
+        buff.marks.insert('c', 5);
+
+        let removed = buff.splice_lines(1, 3, vec![b"new".to_vec()]);
+
+        assert_eq!(removed, text_lines(&["two", "three"]));
+        assert_eq!(buff.marks.get(&'a'), Some(&1));
+        assert_eq!(buff.marks.get(&'b'), None);
+        assert_eq!(buff.marks.get(&'c'), Some(&4));
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let filename = "test_files/one.txt";
+        let original = LineBuffer::from_file(filename).unwrap();
+        let mut buff = original.clone();
+        buff.marks.insert('a', 4);
+
+        buff.begin_change();
+        buff.splice_lines(0, 2, vec![]);
+        buff.set_line(0, b"THREE".to_vec());
+        buff.current_line = 1;
+        buff.end_change();
+        assert_eq!(buff.marks.get(&'a'), Some(&2));
+
+        buff.begin_change();
+        assert!(buff.undo());
+        buff.end_change();
+        assert_eq!(buff.lines, original.lines);
+        assert_eq!(buff.marks.get(&'a'), Some(&4));
+        assert_eq!(buff.current_line, 0);
+
+        buff.begin_change();
+        assert!(buff.undo());
+        buff.end_change();
+        assert_eq!(
+            buff.lines,
+            Some(text_lines(&["THREE", "four", "five"]))
+        );
+        assert_eq!(buff.marks.get(&'a'), Some(&2));
+        assert_eq!(buff.current_line, 1);
+    }
+
+    #[test]
+    fn test_undo_nothing() {
+        let mut buff = LineBuffer::empty();
+        assert!(!buff.undo());
+    }
+
     #[test]
     fn test_count_chars() {
         let filename = "test_files/one.txt";
@@ -147,4 +514,88 @@ mod tests {
             "Char count for empty buffer should be none."
         );
     }
+
+    #[rstest]
+    #[case("a\nb\n", false, vec!["a", "b"], LineEnding::Lf, false)]
+    #[case("a\r\nb\r\n", false, vec!["a", "b"], LineEnding::CrLf, false)]
+    #[case("a\r\nb", false, vec!["a", "b"], LineEnding::CrLf, true)]
+    #[case("a\r\nb\n", false, vec!["a\r", "b"], LineEnding::Lf, false)]
+    #[case("a\r\nb\n", true, vec!["a", "b"], LineEnding::Lf, false)]
+    #[case("a\r\nb\r\n", true, vec!["a", "b"], LineEnding::Lf, false)]
+    #[case("a\nb", false, vec!["a", "b"], LineEnding::Lf, true)]
+    #[case("", false, vec![], LineEnding::Lf, false)]
+    fn rstest_split_lines(
+        #[case] text: &str,
+        #[case] strip_trailing_cr: bool,
+        #[case] expected: Vec<&str>,
+        #[case] line_ending: LineEnding,
+        #[case] missing_newline: bool,
+    ) {
+        let file = split_lines(text.as_bytes(), strip_trailing_cr);
+        assert_eq!(file.lines, text_lines(&expected));
+        assert_eq!(file.line_ending, line_ending);
+        assert_eq!(file.missing_newline, missing_newline);
+        assert_eq!(file.byte_count, text.len());
+    }
+
+    #[test]
+    fn test_crlf_round_trip() {
+        let filename = "/tmp/crlf_round_trip.txt";
+        std::fs::write(filename, "one\r\ntwo\r\n").unwrap();
+        let mut buff = LineBuffer::from_file(filename).unwrap();
+        assert_eq!(buff.lines, Some(text_lines(&["one", "two"])));
+        assert_eq!(buff.line_ending, LineEnding::CrLf);
+        assert_eq!(buff.char_length(), Some(10));
+
+        buff.write_range(None, 0, 2, false).unwrap();
+        assert_eq!(std::fs::read_to_string(filename).unwrap(), "one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn test_missing_newline_round_trip() {
+        let filename = "/tmp/missing_newline_round_trip.txt";
+        std::fs::write(filename, "one\ntwo").unwrap();
+        let mut buff = LineBuffer::from_file(filename).unwrap();
+        assert!(buff.missing_newline);
+        assert_eq!(buff.char_length(), Some(7));
+
+        buff.write_range(None, 0, 2, false).unwrap();
+        assert_eq!(std::fs::read_to_string(filename).unwrap(), "one\ntwo");
+        assert_eq!(buff.range_byte_count(1, 2), 3);
+        buff.write_range(None, 0, 1, false).unwrap();
+        assert_eq!(std::fs::read_to_string(filename).unwrap(), "one\n");
+
+        buff.missing_newline = false;
+        buff.write_range(None, 0, 2, false).unwrap();
+        assert_eq!(std::fs::read_to_string(filename).unwrap(), "one\ntwo\n");
+        assert_eq!(buff.char_length(), Some(8));
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let filename = "/tmp/binary_round_trip.txt";
+        let contents = b"caf\xe9\n\0\xff\xfe\r\n\nend";
+        std::fs::write(filename, contents).unwrap();
+        let mut buff = LineBuffer::from_file(filename).unwrap();
+        assert_eq!(buff.len(), 4);
+        assert_eq!(buff.lines.as_ref().unwrap()[0], b"caf\xe9");
+        assert_eq!(buff.lines.as_ref().unwrap()[1], b"\0\xff\xfe\r");
+        assert_eq!(buff.char_length(), Some(contents.len()));
+
+        buff.write_range(None, 0, 4, false).unwrap();
+        assert_eq!(std::fs::read(filename).unwrap(), contents);
+    }
+
+    #[test]
+    fn test_strip_carriage_returns() {
+        let filename = "/tmp/strip_carriage_returns.txt";
+        std::fs::write(filename, "one\r\ntwo\n").unwrap();
+        let mut buff = LineBuffer::from_file(filename).unwrap();
+        assert_eq!(buff.lines, Some(text_lines(&["one\r", "two"])));
+
+        buff.strip_carriage_returns();
+        assert_eq!(buff.lines, Some(text_lines(&["one", "two"])));
+        assert_eq!(buff.line_ending, LineEnding::Lf);
+        assert!(!buff.modified);
+    }
 }
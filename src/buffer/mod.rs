@@ -1 +1,2 @@
+pub mod journal;
 pub mod line_array_buffer;
@@ -21,6 +21,7 @@ Options:
   -s, --script               suppress byte counts and '!' prompt
   -v, --verbose              be verbose; equivalent to the 'H' command
       --strip-trailing-cr    strip carriage returns at end of text lines
+      --keep-missing-newline don't add a newline missing from the end of a file
       --unsafe-names         allow control characters 1-31 in file names
 
 Start edit by reading in 'file' if given.
@@ -32,6 +33,11 @@ pub struct EdArgs {
     pub filename: Option<String>,
     pub prompt: Option<String>,
     pub verbose: bool,
+    pub script: bool,
+    pub loose_exit_status: bool,
+    pub restricted: bool,
+    pub strip_trailing_cr: bool,
+    pub keep_missing_newline: bool,
     pub debug: bool,
     pub help: bool,
     pub diagnostics: bool,
@@ -50,11 +56,22 @@ pub fn parse_args(arg_list: Vec<OsString>) -> Result<EdArgs, pico_args::Error> {
     }
 
     let diagnostics = pargs.contains("--diagnostics");
+    let verbose = pargs.contains(["-v", "--verbose"]);
+    let script = pargs.contains(["-s", "--script"]);
+    let loose_exit_status = pargs.contains(["-l", "--loose-exit-status"]);
+    let restricted = pargs.contains(["-r", "--restricted"]);
+    let strip_trailing_cr = pargs.contains("--strip-trailing-cr");
+    let keep_missing_newline = pargs.contains("--keep-missing-newline");
 
     let args = EdArgs {
         prompt: pargs.opt_value_from_str(["-p", "--prompt"])?,
         debug: false,
-        verbose: false,
+        verbose,
+        script,
+        loose_exit_status,
+        restricted,
+        strip_trailing_cr,
+        keep_missing_newline,
         filename: pargs.opt_free_from_str()?,
         help: false,
         diagnostics,
@@ -77,25 +94,21 @@ mod tests {
 
     #[test]
     fn test_help() {
-        let test_args = vec!["ed", "--help"].iter().map(OsString::from).collect();
+        let test_args = ["ed", "--help"].iter().map(OsString::from).collect();
         let result = parse_args(test_args);
         assert!(result.is_ok());
     }
 
     #[test]
     fn test_filename() {
-        let test_args = vec!["ed", "/tmp/foo"].iter().map(OsString::from).collect();
+        let test_args = ["ed", "/tmp/foo"].iter().map(OsString::from).collect();
         let result = parse_args(test_args).expect("Error running filename test");
-        if let Some(filename) = result.filename {
-            assert_eq!(filename, "/tmp/foo");
-        } else {
-            assert!(false);
-        }
+        assert_eq!(result.filename.as_deref(), Some("/tmp/foo"));
     }
 
     #[test]
     fn test_no_args() {
-        let test_args = vec!["ed"].iter().map(OsString::from).collect();
+        let test_args = ["ed"].iter().map(OsString::from).collect();
         let result = parse_args(test_args).expect("Error running filename test");
         assert!(result.filename.is_none());
     }
@@ -107,6 +120,21 @@ mod tests {
     // long and short promt arg
     #[case(vec!["ed", "--prompt", "> "], EdArgs{prompt: Some("> ".to_string()), ..Default::default()})]
     #[case(vec!["ed", "-p", "> "], EdArgs{prompt: Some("> ".to_string()), ..Default::default()})]
+    // verbose turns on error explanations
+    #[case(vec!["ed", "-v"], EdArgs{verbose: true, ..Default::default()})]
+    #[case(vec!["ed", "--verbose", "/tmp/foo"], EdArgs{verbose: true, filename: Some("/tmp/foo".to_string()), ..Default::default()})]
+    // script mode
+    #[case(vec!["ed", "-s", "/tmp/foo"], EdArgs{script: true, filename: Some("/tmp/foo".to_string()), ..Default::default()})]
+    #[case(vec!["ed", "--script"], EdArgs{script: true, ..Default::default()})]
+    // restricted mode
+    #[case(vec!["ed", "-r"], EdArgs{restricted: true, ..Default::default()})]
+    #[case(vec!["ed", "--restricted", "notes.txt"], EdArgs{restricted: true, filename: Some("notes.txt".to_string()), ..Default::default()})]
+    // line endings
+    #[case(vec!["ed", "--strip-trailing-cr", "dos.txt"], EdArgs{strip_trailing_cr: true, filename: Some("dos.txt".to_string()), ..Default::default()})]
+    #[case(vec!["ed", "--keep-missing-newline"], EdArgs{keep_missing_newline: true, ..Default::default()})]
+    // exit status
+    #[case(vec!["ed", "-l"], EdArgs{loose_exit_status: true, ..Default::default()})]
+    #[case(vec!["ed", "--loose-exit-status", "-s"], EdArgs{loose_exit_status: true, script: true, ..Default::default()})]
     // No args is a valid case. It should return the default args.
     #[case(vec!["ed"], EdArgs{..Default::default()})]
     // filename and prompt args
@@ -1,3 +1,5 @@
+use crate::ed_command_error::EdCommandError;
+
 /// Represents an address in the `ed` editor.
 /// 
 ///
@@ -6,12 +8,20 @@
 /// * `Abolute(usize)` - An absolute one-indexed linenumber reference.
 /// * `Last` - The last line in the buffer (`$`).
 /// * `Current` - Current line in buffer (`.`, default for most cases).
-/// * `None` - No address.
+/// * `Offset(Box<Address>, isize)` - A base address moved by a number of lines (`.-3`, `$-2`, `++`).
+/// * `ForwardSearch(Vec<u8>)` - The next line matching a regular expression (`/re/`). An empty pattern reuses the last one.
+/// * `BackwardSearch(Vec<u8>)` - The previous line matching a regular expression (`?re?`). An empty pattern reuses the last one.
+/// * `Mark(char)` - The line marked with a lowercase letter by the `k` command (`'x`).
+/// * `None` - No address was given. Resolves to the current line unless the command has another default.
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Clone)]
 pub enum Address {
     Current,
     Absolute(usize),
     Last,
+    Offset(Box<Address>, isize),
+    ForwardSearch(Vec<u8>),
+    BackwardSearch(Vec<u8>),
+    Mark(char),
     None,
 }
 
@@ -41,7 +51,7 @@ impl Address {
 ///
 /// * `Comma` - Preserves current line.
 /// * `Semicolon` - Sets current line to first value in range.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub enum RangeSep {
     Comma,
     Semicolon,
@@ -60,7 +70,9 @@ pub enum RangeSep {
 /// * `address2` - The second address in the command. This can represent the end point of a range.
 /// * `range_sep` - The separator used between the two addresses, typically a comma (`,`) or semicolon (`;`).
 /// * `command` - An optional `String` representing the command to be executed.
-/// * `command_args` - An optional `String` representing the arguments to the command.
+/// * `destination` - The destination address of the `m` and `t` commands.
+/// * `command_args` - The optional bytes of the arguments to the command. They are only required to be UTF-8 where the
+///   command needs text, such as a filename; patterns and replacements may hold any bytes.
 ///
 /// # Example
 ///
@@ -70,26 +82,77 @@ pub enum RangeSep {
 ///     address2: Address::Absolute(5),
 ///     range_sep: RangeSep::Comma,
 ///     command: Some("d".to_string()),
+///     destination: Address::None,
 ///     command_args: None,
 /// };
 /// ```
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct EdCommand {
     pub address1: Address,
     pub address2: Address,
     pub range_sep: RangeSep,
     pub command: Option<String>,
-    pub command_args: Option<String>,
+    pub destination: Address,
+    pub command_args: Option<Vec<u8>>,
 }
 
 impl EdCommand {
-    pub fn default() -> EdCommand {
+    /// Returns the arguments of a command that takes text, such as a
+    /// filename or a shell command.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Option<&str>, EdCommandError>` - The arguments, `None` if there are none, or
+    ///   `EdCommandError::InvalidCommandSuffix` if they are not UTF-8.
+    pub fn text_args(&self) -> Result<Option<&str>, EdCommandError> {
+        self.command_args
+            .as_deref()
+            .map(|args| std::str::from_utf8(args).map_err(|_| EdCommandError::InvalidCommandSuffix))
+            .transpose()
+    }
+}
+
+impl Default for EdCommand {
+    fn default() -> EdCommand {
         EdCommand {
-            address1: Address::Current,
-            address2: Address::Current,
+            address1: Address::None,
+            address2: Address::None,
             range_sep: RangeSep::Comma,
             command: None,
+            destination: Address::None,
             command_args: None,
         }
     }
 }
+
+/// Print suffix that may follow a command, printing the current line afterwards.
+///
+/// # Values
+///
+/// * `Print` - Print the line (`p`).
+/// * `Number` - Print the line with its line number (`n`).
+/// * `List` - Print the line unambiguously (`l`).
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum PrintSuffix {
+    Print,
+    Number,
+    List,
+}
+
+/// A parsed substitute command, saved so that it can be repeated.
+///
+/// # Fields
+///
+/// * `pattern` - The bytes of the regular expression to replace.
+/// * `replacement` - The bytes of the replacement, with `&`, `\1`-`\9` and escapes still unexpanded.
+/// * `global` - Replace every match on the line instead of one (`g`).
+/// * `occurrence` - Replace starting from this match on the line (`N`, default 1).
+/// * `suffix` - Optional print suffix (`p`, `l` or `n`).
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Substitution {
+    pub pattern: Vec<u8>,
+    pub replacement: Vec<u8>,
+    pub global: bool,
+    pub occurrence: usize,
+    pub suffix: Option<PrintSuffix>,
+}
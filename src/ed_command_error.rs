@@ -1,4 +1,5 @@
 use crate::ed_command_parser::Rule;
+use pest::error::ErrorVariant;
 use std::fmt;
 
 
@@ -8,10 +9,54 @@ use std::fmt;
 ///
 /// * `InvalidRange` - Indicates that the specified range in the command is invalid, such as when the first address is greater than the second or the address is out of bounds.
 /// * `EmptyBuffer` - Indicates that an operation was attempted on an empty buffer.
+/// * `NoMatch` - A regular expression search found no matching line.
+/// * `NoPreviousPattern` - An empty pattern was used before any pattern was given.
+/// * `InvalidPattern` - A regular expression could not be compiled.
+/// * `InvalidMarkCharacter` - The `k` command was not given a single lowercase letter.
+/// * `UnknownMark` - A `'x` address refers to a mark that is unset or whose line was deleted.
+/// * `MissingPatternDelimiter` - A substitute pattern was not closed by its delimiter.
+/// * `InvalidPatternDelimiter` - A substitute command used a space, newline or alphanumeric delimiter.
+/// * `InvalidCommandSuffix` - Unexpected characters followed a command.
+/// * `NoPreviousSubstitution` - `s` or `%` was used before any substitution was made.
+/// * `NestedGlobal` - A global command was run from inside another global command list.
+/// * `NoPreviousCommand` - `&` was entered in an interactive global command before any command.
+/// * `NothingToUndo` - `u` was used before any command changed the buffer.
+/// * `InvalidDestination` - The destination of `m` is inside the range being moved.
+/// * `NothingToPut` - `x` was used while the cut buffer is empty.
+/// * `NoCurrentFilename` - A file command needed the default filename, but none is set.
+/// * `BufferModified` - The buffer has unsaved changes; repeating the command discards them.
+/// * `UnknownCommand` - The command letter is not recognized.
+/// * `UnexpectedAddress` - An address was given to a command that does not take one.
+/// * `CannotOpenInputFile` - A file could not be read.
+/// * `CannotOpenOutputFile` - A file could not be written.
+/// * `ShellAccessRestricted` - A shell command was used in restricted mode.
+/// * `DirectoryAccessRestricted` - A filename outside the current directory was used in restricted mode.
 #[derive(Debug)]
 pub enum EdCommandError {
     InvalidRange,
     EmptyBuffer,
+    NoMatch,
+    NoPreviousPattern,
+    InvalidPattern(regex::Error),
+    InvalidMarkCharacter,
+    UnknownMark,
+    MissingPatternDelimiter,
+    InvalidPatternDelimiter,
+    InvalidCommandSuffix,
+    NoPreviousSubstitution,
+    NestedGlobal,
+    NoPreviousCommand,
+    NothingToUndo,
+    InvalidDestination,
+    NothingToPut,
+    NoCurrentFilename,
+    BufferModified,
+    UnknownCommand,
+    UnexpectedAddress,
+    CannotOpenInputFile(std::io::Error),
+    CannotOpenOutputFile(std::io::Error),
+    ShellAccessRestricted,
+    DirectoryAccessRestricted,
     InputModeError(rustyline::error::ReadlineError),
     ParseError(Box<pest::error::Error<Rule>>),
 }
@@ -25,22 +70,95 @@ impl From<rustyline::error::ReadlineError> for EdCommandError {
 }
 
 impl fmt::Display for EdCommandError {
-    /// Formats the error message for display.
+    /// Formats the error message for display, using the wording of ed's
+    /// error explanations.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            EdCommandError::InvalidRange => write!(f, "Invalid Range"),
-            EdCommandError::EmptyBuffer => write!(f, "Empty Buffer"),
-            EdCommandError::InputModeError(ref e) => write!(f, "Input Error: {}", e),
-            EdCommandError::ParseError(ref e) => write!(f, "Parse Error: {}", e),
+            EdCommandError::InvalidRange => write!(f, "Invalid address"),
+            EdCommandError::EmptyBuffer => write!(f, "Invalid address"),
+            EdCommandError::NoMatch => write!(f, "No match"),
+            EdCommandError::NoPreviousPattern => write!(f, "No previous pattern"),
+            EdCommandError::InvalidPattern(ref e) => write!(f, "{}", pattern_error_message(e)),
+            EdCommandError::InvalidMarkCharacter => write!(f, "Invalid mark character"),
+            EdCommandError::UnknownMark => write!(f, "Invalid address"),
+            EdCommandError::MissingPatternDelimiter => write!(f, "Missing pattern delimiter"),
+            EdCommandError::InvalidPatternDelimiter => write!(f, "Invalid pattern delimiter"),
+            EdCommandError::InvalidCommandSuffix => write!(f, "Invalid command suffix"),
+            EdCommandError::NoPreviousSubstitution => write!(f, "No previous substitution"),
+            EdCommandError::NestedGlobal => write!(f, "Cannot nest global commands"),
+            EdCommandError::NoPreviousCommand => write!(f, "No previous command"),
+            EdCommandError::NothingToUndo => write!(f, "Nothing to undo"),
+            EdCommandError::InvalidDestination => write!(f, "Invalid destination"),
+            EdCommandError::NothingToPut => write!(f, "Nothing to put"),
+            EdCommandError::NoCurrentFilename => write!(f, "No current filename"),
+            EdCommandError::BufferModified => write!(f, "Warning: buffer modified"),
+            EdCommandError::UnknownCommand => write!(f, "Unknown command"),
+            EdCommandError::UnexpectedAddress => write!(f, "Unexpected address"),
+            EdCommandError::CannotOpenInputFile(_) => write!(f, "Cannot open input file"),
+            EdCommandError::CannotOpenOutputFile(_) => write!(f, "Cannot open output file"),
+            EdCommandError::ShellAccessRestricted => write!(f, "Shell access restricted"),
+            EdCommandError::DirectoryAccessRestricted => write!(f, "Directory access restricted"),
+            EdCommandError::InputModeError(ref e) => write!(f, "Input error: {}", e),
+            EdCommandError::ParseError(ref e) => write!(f, "{}", parse_error_message(e)),
         }
     }
 }
 
+/// Returns a one-line message for a regular expression that does not
+/// compile, such as "Unclosed group", instead of the regex crate's
+/// multi-line report.
+fn pattern_error_message(error: &regex::Error) -> String {
+    let message = match error {
+        regex::Error::Syntax(report) => report
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix("error: "))
+            .unwrap_or("invalid pattern"),
+        regex::Error::CompiledTooBig(_) => "pattern too big",
+        _ => "invalid pattern",
+    };
+    let mut chars = message.chars();
+    chars
+        .next()
+        .map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+}
+
+/// Returns the message for a command line the parser rejected, chosen by
+/// the part of the line it expected next.
+fn parse_error_message(error: &pest::error::Error<Rule>) -> &'static str {
+    let ErrorVariant::ParsingError { positives, .. } = &error.variant else {
+        return "Invalid command suffix";
+    };
+    let expects = |rules: &[Rule]| positives.iter().any(|rule| rules.contains(rule));
+    if expects(&[Rule::mark]) {
+        "Invalid mark character"
+    } else if expects(&[Rule::forward_pattern, Rule::backward_pattern]) {
+        "Missing pattern delimiter"
+    } else if expects(&[
+        Rule::range,
+        Rule::address,
+        Rule::destination,
+        Rule::current,
+        Rule::last,
+        Rule::absolute,
+        Rule::offset,
+    ]) {
+        "Invalid address"
+    } else if expects(&[Rule::command, Rule::transfer]) {
+        "Unknown command"
+    } else {
+        "Invalid command suffix"
+    }
+}
+
 impl std::error::Error for EdCommandError {
-    /// Returns the source of the error, if any. In this case, no underlying error is wrapped, so it returns `None`.
+    /// Returns the underlying error, if any, such as the parser or I/O error.
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match *self {
             EdCommandError::ParseError(ref e) => Some(e),
+            EdCommandError::InvalidPattern(ref e) => Some(e),
+            EdCommandError::CannotOpenInputFile(ref e) => Some(e),
+            EdCommandError::CannotOpenOutputFile(ref e) => Some(e),
             _ => None,
         }
     }
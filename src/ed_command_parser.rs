@@ -1,19 +1,79 @@
+use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 use crate::command_structs::*;
 
 use crate::ed_command_error::EdCommandError;
+use crate::search::read_pattern;
 
 #[derive(Parser)]
 #[grammar = "ed_command.pest"] // Adjust the grammar path as necessary
 pub struct EdCommandParser;
 
+/// Converts an `address` pair into an `Address`, folding any trailing
+/// `+n`, `-n` or `^n` offsets into a single `Address::Offset`.
+///
+/// A bare `+` or `-` counts as one line, repeated offsets accumulate
+/// (`--` is `-2`), and offsets without a base address are relative to the
+/// current line.
+///
+/// # Arguments
+///
+/// * `pair` - A `Rule::address` pair produced by the parser.
+///
+/// # Returns
+///
+/// * `Result<Address, EdCommandError>` - The parsed address, or `EdCommandError::InvalidRange` if a
+///   line number or offset is too large.
+fn parse_address(pair: Pair<Rule>) -> Result<Address, EdCommandError> {
+    let mut base = Address::Current;
+    let mut offset: isize = 0;
+    let mut has_offset = false;
+
+    for inner in pair.into_inner() {
+        match inner.as_rule() {
+            Rule::offset => {
+                has_offset = true;
+                let text = inner.as_str();
+                let amount = match &text[1..] {
+                    "" => 1,
+                    digits => digits
+                        .parse::<isize>()
+                        .map_err(|_| EdCommandError::InvalidRange)?,
+                };
+                offset = if text.starts_with('+') {
+                    offset.checked_add(amount)
+                } else {
+                    offset.checked_sub(amount)
+                }
+                .ok_or(EdCommandError::InvalidRange)?;
+            }
+            Rule::forward_search => {
+                base = Address::ForwardSearch(search_pattern(inner.as_str(), '/'));
+            }
+            Rule::backward_search => {
+                base = Address::BackwardSearch(search_pattern(inner.as_str(), '?'));
+            }
+            Rule::mark => {
+                base = Address::Mark(inner.as_str().chars().nth(1).unwrap_or('a'));
+            }
+            _ => base = Address::from_str(inner.as_str()).ok_or(EdCommandError::InvalidRange)?,
+        }
+    }
+
+    if has_offset {
+        Ok(Address::Offset(Box::new(base), offset))
+    } else {
+        Ok(base)
+    }
+}
+
 /// Parses a string input into a tuple representing a range of addresses with a separator.
 ///
 /// # Arguments
 ///
 /// * `input` - A string slice that holds the input to be parsed. This input should represent
-///     a range in the form of two addresses separated by a comma (`,`), semicolon (`;`), or no separator.
+///   a range in the form of two addresses separated by a comma (`,`), semicolon (`;`), or no separator.
 ///
 /// # Returns
 ///
@@ -50,9 +110,9 @@ fn parse_range(
         match pair.as_rule() {
             Rule::address => {
                 if range_separator_present {
-                    address2 = Address::from_str(pair.as_str()).unwrap();
+                    address2 = parse_address(pair)?;
                 } else {
-                    address1 = Address::from_str(pair.as_str()).unwrap();
+                    address1 = parse_address(pair)?;
                 }
             }
 
@@ -88,29 +148,65 @@ fn parse_range(
     Ok((address1, separator, address2))
 }
 
+/// Returns the bytes of text made by `parse_line`, where each character
+/// stands for one byte of the command line.
+fn text_bytes(text: &str) -> Vec<u8> {
+    text.chars().map(|c| c as u8).collect()
+}
+
+/// Returns the pattern of a `/re/` or `?re?` address, read with
+/// `read_pattern` so that it is unescaped like the patterns of `s` and `g`.
+fn search_pattern(search: &str, delimiter: char) -> Vec<u8> {
+    let bytes = text_bytes(search);
+    let mut text = &bytes[1..];
+    read_pattern(&mut text, delimiter).0
+}
+
+/// Parses one command line into an `EdCommand`.
+///
+/// The line is bytes and need not be UTF-8. It is parsed as text with one
+/// character for each byte, so search patterns and arguments keep exactly
+/// the bytes that were typed.
+///
+/// # Arguments
+///
+/// * `input` - The bytes of the command line, without its trailing newline.
+///
+/// # Returns
+///
+/// * `Result<EdCommand, EdCommandError>` - The parsed command, `EdCommandError::ParseError` if the line
+///   is not a command, or `EdCommandError::InvalidRange` if an address is too large.
 pub fn parse_line(
-    input: &str,
+    input: &[u8],
 ) -> Result<EdCommand, EdCommandError> {
-    let pairs = EdCommandParser::parse(Rule::line, input)
+    let input: String = input.iter().map(|&byte| byte as char).collect();
+    let pairs = EdCommandParser::parse(Rule::line, &input)
         .map_err(|e| EdCommandError::ParseError(Box::new(e)))?
         .next()
         .unwrap()
         .into_inner();
-    let mut address1 = Address::Current;
+    let mut address1 = Address::None;
     let mut range_sep = RangeSep::Comma;
     let mut address2 = Address::None;
     let mut command = None;
+    let mut destination = Address::None;
     let mut command_args = None;
     for pair in pairs {
         match pair.as_rule() {
-            Rule::range => {
+            // an empty range leaves both addresses as `Address::None`
+            Rule::range if !pair.as_str().is_empty() => {
                 (address1, range_sep, address2) = parse_range(pair.as_str())?;
             }
-            Rule::command => {
+            Rule::command | Rule::transfer => {
                 command = Some(String::from(pair.as_str()));
             }
+            Rule::destination => {
+                if let Some(address) = pair.into_inner().next() {
+                    destination = parse_address(address)?;
+                }
+            }
             Rule::arg => {
-                command_args = Some(String::from(pair.as_str()));
+                command_args = Some(text_bytes(pair.as_str()));
             }
             _ => (),
         }
@@ -120,6 +216,7 @@ pub fn parse_line(
         address2,
         range_sep,
         command,
+        destination,
         command_args,
     })
 }
@@ -159,6 +256,28 @@ mod tests {
     #[case("", (Address::Current, RangeSep::Comma, Address::Current), "empty string")]
     #[case(";", (Address::Current, RangeSep::Semicolon, Address::Last), "; (current->end)")]
     #[case("%", (Address::Absolute(1), RangeSep::Comma, Address::Last), "% (all lines)")]
+    #[case(".-3,.+3", (Address::Offset(Box::new(Address::Current), -3), RangeSep::Comma,
+        Address::Offset(Box::new(Address::Current), 3)), "offsets from current")]
+    #[case("$-5,$", (Address::Offset(Box::new(Address::Last), -5), RangeSep::Comma, Address::Last),
+        "offset from last")]
+    #[case("7+3", (Address::Offset(Box::new(Address::Absolute(7)), 3), RangeSep::Comma,
+        Address::Offset(Box::new(Address::Absolute(7)), 3)), "offset from absolute")]
+    #[case("++", (Address::Offset(Box::new(Address::Current), 2), RangeSep::Comma,
+        Address::Offset(Box::new(Address::Current), 2)), "repeated plus accumulates")]
+    #[case("-,^2", (Address::Offset(Box::new(Address::Current), -1), RangeSep::Comma,
+        Address::Offset(Box::new(Address::Current), -2)), "bare minus and caret")]
+    #[case("$--+4", (Address::Offset(Box::new(Address::Last), 2), RangeSep::Comma,
+        Address::Offset(Box::new(Address::Last), 2)), "mixed offset chain")]
+    #[case("/foo/", (Address::ForwardSearch(b"foo".to_vec()), RangeSep::Comma,
+        Address::ForwardSearch(b"foo".to_vec())), "forward search")]
+    #[case("?bar?,/a\\/b", (Address::BackwardSearch(b"bar".to_vec()), RangeSep::Comma,
+        Address::ForwardSearch(b"a/b".to_vec())), "backward search, escaped and unterminated forward")]
+    #[case("?a\\?b?,?c", (Address::BackwardSearch(b"a\\?b".to_vec()), RangeSep::Comma,
+        Address::BackwardSearch(b"c".to_vec())), "escaped and unterminated backward search")]
+    #[case("//+1", (Address::Offset(Box::new(Address::ForwardSearch(vec![])), 1), RangeSep::Comma,
+        Address::Offset(Box::new(Address::ForwardSearch(vec![])), 1)), "empty pattern with offset")]
+    #[case("'a,'b-1", (Address::Mark('a'), RangeSep::Comma,
+        Address::Offset(Box::new(Address::Mark('b')), -1)), "marks")]
     fn test_parameterized_range_parse(
         #[case] input: &str,
         #[case] expected: (Address, RangeSep, Address),
@@ -169,6 +288,16 @@ mod tests {
         Ok(())
     }
 
+    #[rstest]
+    #[case("99999999999999999999999p")]
+    #[case("+99999999999999999999p")]
+    #[case("+9223372036854775807+9p")]
+    #[case("-9223372036854775807-9p")]
+    #[case("1m99999999999999999999999")]
+    fn rstest_address_too_large(#[case] input: &str) {
+        assert!(matches!(parse_line(input.as_bytes()), Err(EdCommandError::InvalidRange)));
+    }
+
     #[rstest]
     #[case("50", Address::Absolute(50), "'50' matches 50")]
     #[case("5", Address::Absolute(5), "'5' matches 5")]
@@ -202,6 +331,10 @@ mod tests {
     #[case("i", "i", "'i' matches 'i'")]
     #[case("=", "=", "'=' matches '='")]
     #[case("d", "d", "'d' (delete)")]
+    #[case("k", "k", "'k' (mark)")]
+    #[case("g", "g", "'g' (global)")]
+    #[case("v", "v", "'v' (inverse global)")]
+    #[case("s", "s", "'s' (substitute)")]
     fn test_parameterized_command_parse(
         #[case] input: &str,
         #[case] expected: &str,
@@ -231,7 +364,7 @@ mod tests {
     }, "write and quit command")]
     #[case("wfoo.txt", EdCommand{
         command: Some(String::from("w")),
-        command_args: Some(String::from("foo.txt")),
+        command_args: Some(b"foo.txt".to_vec()),
         ..EdCommand::default()
         
     }, "write with args")]
@@ -241,12 +374,62 @@ mod tests {
         ..EdCommand::default()
         
     }, "no command, first and last address")]
+    #[case("/x/,?y?p", EdCommand{
+        address1: Address::ForwardSearch(b"x".to_vec()),
+        address2: Address::BackwardSearch(b"y".to_vec()),
+        command: Some(String::from("p")),
+        ..EdCommand::default()
+    }, "search range with command")]
+    #[case("5s/foo123/123foo/", EdCommand{
+        address1: Address::Absolute(5),
+        address2: Address::Absolute(5),
+        command: Some(String::from("s")),
+        command_args: Some(b"/foo123/123foo/".to_vec()),
+        ..EdCommand::default()
+    }, "substitute")]
+    #[case("s/a/b\\\nc/", EdCommand{
+        command: Some(String::from("s")),
+        command_args: Some(b"/a/b\\\nc/".to_vec()),
+        ..EdCommand::default()
+    }, "substitute with escaped newline")]
+    #[case("1,2m$", EdCommand{
+        address1: Address::Absolute(1),
+        address2: Address::Absolute(2),
+        command: Some(String::from("m")),
+        destination: Address::Last,
+        ..EdCommand::default()
+    }, "move with destination")]
+    #[case("t0p", EdCommand{
+        command: Some(String::from("t")),
+        destination: Address::Absolute(0),
+        command_args: Some(b"p".to_vec()),
+        ..EdCommand::default()
+    }, "transfer to address 0 with suffix")]
+    #[case("m", EdCommand{
+        command: Some(String::from("m")),
+        ..EdCommand::default()
+    }, "move without destination")]
+    #[case("'a,'bt/x/-1n", EdCommand{
+        address1: Address::Mark('a'),
+        address2: Address::Mark('b'),
+        command: Some(String::from("t")),
+        destination: Address::Offset(Box::new(Address::ForwardSearch(b"x".to_vec())), -1),
+        command_args: Some(b"n".to_vec()),
+        ..EdCommand::default()
+    }, "transfer to search address with offset")]
+    #[case("2ka", EdCommand{
+        address1: Address::Absolute(2),
+        address2: Address::Absolute(2),
+        command: Some(String::from("k")),
+        command_args: Some(b"a".to_vec()),
+        ..EdCommand::default()
+    }, "mark letter is an argument, not a second command")]
     fn test_parameterized_line_parse_to_command(
         #[case] input: &str,
         #[case] expected: EdCommand,
         #[case] note: &str,
     ) {
-        let result = parse_line(input).expect("bad line parse");
+        let result = parse_line(input.as_bytes()).expect("bad line parse");
         assert_eq!(result, expected, "{}", note);
     }
 }
@@ -1,9 +1,17 @@
 use crate::buffer::line_array_buffer::LineBuffer;
-use crate::command_structs::{Address, EdCommand};
+use crate::command_structs::{Address, EdCommand, PrintSuffix, RangeSep};
+use crate::global::{global, interactive_global};
+use crate::input_mode::LineSource;
+use crate::list::{list_line, LIST_WIDTH};
 use crate::modify::*;
+use crate::buffer::line_array_buffer::{byte_count, read_lines, FileLines, LineEnding};
+use crate::search::{remember_pattern, search_backward, search_forward};
+use crate::shell::{command_input, command_output, expand_command, filter_lines, run_shell};
+use crate::substitute::substitute;
 use crate::ed_command_error::EdCommandError;
 
 use std::error::Error;
+use std::io::Write;
 
 /// Signal for REPL to `Continue` or `Quit`.
 ///
@@ -17,8 +25,59 @@ pub enum REPLStatus {
     Quit,
 }
 
+/// Resolves an `Address` to a concrete one-indexed line number.
+///
+/// # Arguments
+///
+/// * `address` - The `Address` to resolve.
+/// * `buffer` - A reference to the `LineBuffer` the address refers to.
+///
+/// # Return Value
+///
+/// Returns `Result<usize, EdCommandError>`, where:
+/// * `Ok(line)` is a line number in the range `0..=buffer.len()`.
+/// * An `EdCommandError::InvalidRange` error is returned if the address, including any offset, falls outside the buffer.
+/// * An `EdCommandError::NoMatch` error is returned if a search address finds no matching line.
+/// * An `EdCommandError::UnknownMark` error is returned if a mark address is not set.
+pub fn resolve_address(address: &Address, buffer: &LineBuffer) -> Result<usize, EdCommandError> {
+    let line = match address {
+        Address::Current | Address::None => buffer.current_line,
+        Address::Last => buffer.len(),
+        Address::Absolute(addr) => *addr,
+        Address::Offset(base, offset) => resolve_address(base, buffer)?
+            .checked_add_signed(*offset)
+            .ok_or(EdCommandError::InvalidRange)?,
+        Address::ForwardSearch(pattern) => search_forward(pattern, buffer)?,
+        Address::BackwardSearch(pattern) => search_backward(pattern, buffer)?,
+        Address::Mark(name) => *buffer.marks.get(name).ok_or(EdCommandError::UnknownMark)?,
+    };
+
+    if line > buffer.len() {
+        return Err(EdCommandError::InvalidRange);
+    }
+    Ok(line)
+}
+
+/// Saves the pattern of a search address, including one under an offset,
+/// as the last pattern.
+fn remember_address_pattern(address: &Address, buffer: &mut LineBuffer) {
+    match address {
+        Address::ForwardSearch(pattern) | Address::BackwardSearch(pattern) => {
+            remember_pattern(pattern, buffer)
+        }
+        Address::Offset(base, _) => remember_address_pattern(base, buffer),
+        _ => {}
+    }
+}
+
 /// Validates that addresses provided with command are within buffer bounds and in the correct order.
-/// `Absolute(0) <= command.address1 <= buffer.current <= buffer.len()`
+/// `Absolute(0) <= command.address1 <= command.address2 <= buffer.len()`
+///
+/// Relative and search addresses, including the destination of `m` and `t`,
+/// are resolved to `Address::Absolute` values.
+/// When the range separator is `;`, the current line is set to the first
+/// address before the second address is resolved. Search patterns are saved
+/// as `buffer.last_pattern`.
 ///
 /// # Arguments
 ///
@@ -27,54 +86,92 @@ pub enum REPLStatus {
 ///
 /// # Return Value
 ///
-/// Returns `Result<(), EdCommandError>`, where:
-/// * `Ok(())` indicates that the range is valid.
+/// Returns `Result<EdCommand, EdCommandError>`, where:
+/// * `Ok(EdCommand)` is a copy of `command` with both addresses resolved to absolute line numbers.
 /// * An `EdCommandError::InvalidRange` error is returned if the addresses specified in the command are out of bounds or the first address is greater than the second address.
-fn validate_range(buffer: &mut LineBuffer, command: &EdCommand) -> Result<(), EdCommandError> {
+fn validate_range(buffer: &mut LineBuffer, command: &EdCommand) -> Result<EdCommand, EdCommandError> {
     if buffer.current_line > buffer.len() {
         buffer.current_line = buffer.len();
     }
 
-    // println!("{}, {}", buffer.current_line, buffer.len());
-
-    let address1 = match command.address1.clone() {
-        Address::Current => Address::Absolute(buffer.current_line),
-        Address::Last => Address::Absolute(buffer.len()),
-        other => other,
-    };
-
-    let address2 = match command.address2.clone() {
-        Address::Current => Address::Absolute(buffer.current_line),
-        Address::Last => Address::Absolute(buffer.len()),
-        other => other,
-    };
+    remember_address_pattern(&command.address1, buffer);
+    let address1 = resolve_address(&command.address1, buffer)?;
+    if command.range_sep == RangeSep::Semicolon {
+        buffer.current_line = address1;
+    }
+    remember_address_pattern(&command.address2, buffer);
+    let address2 = resolve_address(&command.address2, buffer)?;
 
     if address1 > address2 {
         return Err(EdCommandError::InvalidRange);
     }
 
-    // Check if address1 is within the valid range
-    if let Address::Absolute(addr) = address1 {
-        if addr > buffer.len() {
-            return Err(EdCommandError::InvalidRange);
+    let destination = match command.destination {
+        Address::None => Address::None,
+        ref address => {
+            remember_address_pattern(address, buffer);
+            Address::Absolute(resolve_address(address, buffer)?)
         }
-    }
+    };
 
-    // Check if address2 is within the valid range
-    if let Address::Absolute(addr) = address2 {
-        if addr > buffer.len() {
-            return Err(EdCommandError::InvalidRange);
-        }
+    Ok(EdCommand {
+        address1: Address::Absolute(address1),
+        address2: Address::Absolute(address2),
+        destination,
+        ..command.clone()
+    })
+}
+
+/// Fills in the default range of commands that do not work on the current
+/// line when no address is given. `g`, `v`, `G`, `V`, `w`, `W` and `wq`
+/// default to the whole buffer, `r` to the last line, `z` to the next line,
+/// and `j` to the current and next lines.
+fn default_range(command: &EdCommand, buffer: &LineBuffer) -> EdCommand {
+    if command.address1 != Address::None || command.address2 != Address::None {
+        return command.clone();
+    }
+    match command.command.as_deref() {
+        Some("g") | Some("v") | Some("G") | Some("V") => EdCommand {
+            address1: Address::Absolute(1),
+            address2: Address::Last,
+            ..command.clone()
+        },
+        // an empty buffer can still be written
+        Some("w") | Some("W") | Some("wq") => EdCommand {
+            address1: Address::Absolute(buffer.len().min(1)),
+            address2: Address::Last,
+            ..command.clone()
+        },
+        Some("r") => EdCommand {
+            address1: Address::Last,
+            address2: Address::Last,
+            ..command.clone()
+        },
+        Some("z") => EdCommand {
+            address1: Address::Offset(Box::new(Address::Current), 1),
+            address2: Address::Offset(Box::new(Address::Current), 1),
+            ..command.clone()
+        },
+        Some("j") => EdCommand {
+            address1: Address::Current,
+            address2: Address::Offset(Box::new(Address::Current), 1),
+            ..command.clone()
+        },
+        _ => command.clone(),
     }
-    Ok(())
 }
 
 /// Executes the given command on the buffer and returns the result status.
 ///
+/// Changes made to the buffer are recorded as one step for the `u` command.
+/// Commands run from inside a global command list are recorded as part of
+/// the global command.
+///
 /// # Arguments
 ///
 /// * `buffer` - A mutable reference to the `LineBuffer`, which holds the lines of text being edited.
 /// * `command` - A reference to the `EdCommand`, which specifies the command to be executed and its associated parameters.
+/// * `input` - The `LineSource` that input mode reads text from.
 ///
 /// # Return Value
 ///
@@ -85,93 +182,533 @@ fn validate_range(buffer: &mut LineBuffer, command: &EdCommand) -> Result<(), Ed
 pub fn command_runner(
     buffer: &mut LineBuffer,
     command: &EdCommand,
+    input: &mut dyn LineSource,
+) -> Result<REPLStatus, Box<dyn Error>> {
+    // commands run from a global command list are undone with the global command
+    if buffer.global_active {
+        return run_command(buffer, command, input);
+    }
+    buffer.begin_change();
+    let result = run_command(buffer, command, input);
+    buffer.end_change();
+
+    // a command that warned about unsaved changes goes ahead if it is repeated next
+    buffer.modified_warned = matches!(
+        &result,
+        Err(e) if matches!(e.downcast_ref(), Some(EdCommandError::BufferModified))
+    );
+    result
+}
+
+/// Validates the command's range and dispatches it to the function that implements it.
+fn run_command(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    input: &mut dyn LineSource,
 ) -> Result<REPLStatus, Box<dyn Error>> {
-    validate_range(buffer, &command)?;
+    // a line with only an address moves to that line; an empty line does nothing
+    let address_only = command.address2 != Address::None;
+    match command.command.as_deref() {
+        Some("q") | Some("Q") | Some("e") | Some("E") | Some("u") | Some("h") | Some("H")
+        | Some("o")
+            if address_only =>
+        {
+            return Err(Box::new(EdCommandError::UnexpectedAddress))
+        }
+        Some("q") | Some("Q") | Some("h") | Some("H") if !matches!(filename_arg(command), Ok(None)) => {
+            return Err(Box::new(EdCommandError::InvalidCommandSuffix))
+        }
+        None if command.command_args.is_some() => {
+            return Err(Box::new(EdCommandError::UnknownCommand))
+        }
+        _ => {}
+    }
+    if buffer.restricted {
+        check_restricted(command)?;
+    }
+    let command = validate_range(buffer, &default_range(command, buffer))?;
     let repl_status = match command.command.as_deref() {
-        Some("q") => quit(buffer, &command)?,
-        Some("w") => write(buffer, &command)?,
+        Some("q") => quit(buffer, &command, true)?,
+        Some("Q") => quit(buffer, &command, false)?,
+        Some("h") => explain_error(buffer, false),
+        Some("H") => explain_error(buffer, true),
+        Some("w") => write(buffer, &command, false)?,
+        Some("W") => write(buffer, &command, true)?,
         Some("wq") => write_quit(buffer, &command)?,
         Some("p") => print(buffer, &command)?,
-        Some("i") => insert(buffer, &command)?,
+        Some("l") => list(buffer, &command)?,
+        Some("z") => scroll(buffer, &command)?,
+        Some("i") => insert(buffer, &command, input)?,
         Some("=") => print_current_line_number(buffer, &command)?,
-        Some("a") => append(buffer, &command)?,
-        Some("c") => correct(buffer, &command)?,
+        Some("a") => append(buffer, &command, input)?,
+        Some("c") => correct(buffer, &command, input)?,
         Some("d") => delete(buffer, &command)?,
-        Some("n") => print_with_numbers(buffer, &command)?,
+        Some("k") => mark(buffer, &command)?,
+        Some("u") => undo(buffer, &command)?,
+        Some("j") => join(buffer, &command)?,
+        Some("m") => move_lines(buffer, &command)?,
+        Some("t") => transfer(buffer, &command)?,
+        Some("e") => edit(buffer, &command, true)?,
+        Some("E") => edit(buffer, &command, false)?,
+        Some("r") => read(buffer, &command)?,
+        Some("o") => line_endings(buffer, &command)?,
+        Some("!") if address_only => filter(buffer, &command)?,
+        Some("!") => shell_escape(buffer, &command)?,
+        Some("|") => filter(buffer, &command)?,
+        Some("y") => yank(buffer, &command)?,
+        Some("x") => put(buffer, &command)?,
+        Some("s") => substitute(buffer, &command)?,
+        Some("g") => global(buffer, &command, false)?,
+        Some("v") => global(buffer, &command, true)?,
+        Some("G") => interactive_global(buffer, &command, false, input)?,
+        Some("V") => interactive_global(buffer, &command, true, input)?,
+        Some("n") => {
+            let status = print_with_numbers(buffer, &command)?;
+            set_current_line_number(buffer, &command)?;
+            status
+        }
+        None if address_only => goto_line(buffer, &command)?,
         _ => REPLStatus::Continue,
     };
 
     Ok(repl_status)
 }
 
-/// Quits the editor, performing any necessary cleanup before exiting.
+/// Reports a failed command the way ed does, by printing `?`. The error is
+/// saved for `h`, and its explanation is printed as well when `H` is on.
 ///
 /// # Arguments
 ///
-/// * `_buffer` - An unused mutable reference to the `LineBuffer`.
-/// * `_command` - An unused reference to the `EdCommand`.
+/// * `buffer` - A mutable reference to the `LineBuffer`, which keeps the last error.
+/// * `error` - The error returned by the command or the parser.
+pub fn report_error(buffer: &mut LineBuffer, error: &dyn Error) {
+    println!("?");
+    buffer.last_error = Some(error.to_string());
+    if buffer.explain_errors {
+        println!("{}", error);
+    }
+}
+
+/// Prints the explanation of the last error (`h`), or toggles printing
+/// explanations for every error (`H`). Turning explanations on also explains
+/// the last error.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which keeps the last error and the `H` setting.
+/// * `toggle` - `true` for `H`, `false` for `h`.
 ///
 /// # Return Value
 ///
-/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Quit)` indicating the editor should exit.
-fn quit(_buffer: &mut LineBuffer, _command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
-    // Perform cleanup or any necessary operations before quitting
+/// Returns `REPLStatus::Continue`.
+fn explain_error(buffer: &mut LineBuffer, toggle: bool) -> REPLStatus {
+    if toggle {
+        buffer.explain_errors = !buffer.explain_errors;
+        if !buffer.explain_errors {
+            return REPLStatus::Continue;
+        }
+    }
+    if let Some(error) = &buffer.last_error {
+        println!("{}", error);
+    }
+    REPLStatus::Continue
+}
 
-    // Exit the program
+/// Quits the editor.
+///
+/// When `check_modified` is set (`q`) and the buffer has unsaved changes,
+/// the first attempt fails with a warning and a second `q` in a row quits;
+/// `Q` never checks.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, checked for unsaved changes.
+/// * `_command` - An unused reference to the `EdCommand`.
+/// * `check_modified` - `true` to refuse once when the buffer has unsaved changes.
+///
+/// # Return Value
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Quit)` indicating the editor should exit,
+/// or `EdCommandError::BufferModified` on a first `q` with unsaved changes.
+fn quit(
+    buffer: &mut LineBuffer,
+    _command: &EdCommand,
+    check_modified: bool,
+) -> Result<REPLStatus, Box<dyn Error>> {
+    if check_modified && buffer.modified && !buffer.modified_warned {
+        return Err(Box::new(EdCommandError::BufferModified));
+    }
     Ok(REPLStatus::Quit)
 }
 
-/// Writes the buffer to a file and continues editing.
+/// Writes the addressed lines to a file and continues editing, or pipes them
+/// to a shell command when the argument starts with `!`. The number of bytes
+/// written is printed.
+///
+/// Only writing the whole buffer to a file clears the modified flag.
 ///
 /// # Arguments
 ///
 /// * `buffer` - A mutable reference to the `LineBuffer`, which holds the lines of text to be saved.
-/// * `command` - A reference to the `EdCommand`, containing any arguments for the write operation (e.g., file name).
+/// * `command` - A reference to the `EdCommand`, containing the range and any arguments for the write operation (e.g., file name).
+/// * `append` - `true` to append the lines to the file (`W`) instead of replacing it.
 ///
 /// # Return Value
 ///
-/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)` if the buffer is successfully saved, or an error if the save operation fails.
-fn write(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
-    match buffer.save(command.command_args.as_deref()) {
-        Ok(_) => Ok(REPLStatus::Continue),
-        Err(e) => Err(Box::new(e)),
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)` if the lines are successfully saved,
+/// `EdCommandError::InvalidRange` if the range starts at line 0 of a non-empty buffer, `EdCommandError::NoCurrentFilename`
+/// if no file is named or set, or `EdCommandError::CannotOpenOutputFile` if the file cannot be written.
+fn write(buffer: &mut LineBuffer, command: &EdCommand, append: bool) -> Result<REPLStatus, Box<dyn Error>> {
+    let (start, end) = write_range(buffer, command)?;
+    let count = buffer.range_byte_count(start, end);
+    match filename_arg(command)? {
+        Some(shell_command) if shell_command.starts_with('!') => {
+            let lines = buffer.lines.as_deref().unwrap_or_default();
+            command_input(&shell_command[1..], &lines[start..end])?;
+        }
+        None if buffer.filename.is_none() => return Err(Box::new(EdCommandError::NoCurrentFilename)),
+        filename => buffer
+            .write_range(filename, start, end, append)
+            .map_err(EdCommandError::CannotOpenOutputFile)?,
     }
+    print_byte_count(buffer, count);
+    Ok(REPLStatus::Continue)
 }
 
-/// Writes the buffer to a file and then quits the editor.
+/// Writes the addressed lines to a file and then quits the editor.
 ///
 /// # Arguments
 ///
 /// * `buffer` - A mutable reference to the `LineBuffer`, which holds the lines of text to be saved.
-/// * `command` - A reference to the `EdCommand`, containing any arguments for the write operation (e.g., file name).
+/// * `command` - A reference to the `EdCommand`, containing the range and any arguments for the write operation (e.g., file name).
 ///
 /// # Return Value
 ///
-/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Quit)` if the buffer is successfully saved, or an error if the save operation fails.
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Quit)` if the lines are successfully saved, or an error if the save operation fails.
 fn write_quit(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
-    match buffer.save(command.command_args.as_deref()) {
-        Ok(_) => Ok(REPLStatus::Quit),
-        Err(e) => Err(Box::new(e)),
+    write(buffer, command, false)?;
+    Ok(REPLStatus::Quit)
+}
+
+/// Converts the resolved range of a write command to the zero-indexed lines
+/// `start..end`.
+fn write_range(buffer: &LineBuffer, command: &EdCommand) -> Result<(usize, usize), EdCommandError> {
+    let start = address_to_index(command.address1.clone(), buffer)?;
+    let end = address_to_index(command.address2.clone(), buffer)?;
+    match command.address1 {
+        Address::Absolute(0) if buffer.len() > 0 => Err(EdCommandError::InvalidRange),
+        Address::Absolute(0) => Ok((0, 0)),
+        _ => Ok((start, end + 1)),
+    }
+}
+
+/// Refuses the commands that restricted mode does not allow: `!` and `|`,
+/// file commands whose argument is a `!command`, and file commands naming a
+/// file outside the current directory.
+///
+/// # Return Value
+///
+/// Returns `Result<(), EdCommandError>` with `EdCommandError::ShellAccessRestricted` for a shell command,
+/// or `EdCommandError::DirectoryAccessRestricted` for a filename outside the current directory.
+fn check_restricted(command: &EdCommand) -> Result<(), EdCommandError> {
+    match command.command.as_deref() {
+        Some("!") | Some("|") => Err(EdCommandError::ShellAccessRestricted),
+        Some("e") | Some("E") | Some("r") | Some("w") | Some("W") | Some("wq") => {
+            match filename_arg(command)? {
+                Some(arg) if arg.starts_with('!') => Err(EdCommandError::ShellAccessRestricted),
+                Some(filename) => check_filename(filename),
+                None => Ok(()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that a filename names a file in the current directory, as
+/// restricted mode requires. Filenames containing `/`, and `..`, are
+/// rejected.
+///
+/// # Return Value
+///
+/// Returns `Result<(), EdCommandError>` with `EdCommandError::DirectoryAccessRestricted` if the file may be
+/// outside the current directory.
+pub fn check_filename(filename: &str) -> Result<(), EdCommandError> {
+    if filename.contains('/') || filename == ".." {
+        return Err(EdCommandError::DirectoryAccessRestricted);
+    }
+    Ok(())
+}
+
+/// Returns the filename argument of a command with leading blanks removed,
+/// `None` if no filename was given, or `EdCommandError::InvalidCommandSuffix`
+/// if it is not UTF-8.
+pub fn filename_arg(command: &EdCommand) -> Result<Option<&str>, EdCommandError> {
+    match command.text_args()?.map(str::trim_start) {
+        None | Some("") => Ok(None),
+        filename => Ok(filename),
+    }
+}
+
+/// Replaces the buffer with the contents of a file, or with the output of a
+/// shell command when the argument starts with `!`, and prints the number of
+/// bytes read.
+///
+/// A filename becomes the default filename. With no argument the default
+/// file is read again. When `check_modified` is set (`e`) and the buffer has
+/// unsaved changes, the first attempt fails with a warning and a second `e`
+/// in a row goes ahead; `E` never checks.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which will hold the new contents.
+/// * `command` - A reference to the `EdCommand`, containing the filename or `!command` argument.
+/// * `check_modified` - `true` to refuse once when the buffer has unsaved changes.
+///
+/// # Return Value
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)` if the buffer was replaced,
+/// `EdCommandError::BufferModified` on a first `e` with unsaved changes, `EdCommandError::NoCurrentFilename`
+/// if no file is named or set, or the error from reading the file or running the command.
+fn edit(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    check_modified: bool,
+) -> Result<REPLStatus, Box<dyn Error>> {
+    if check_modified && buffer.modified && !buffer.modified_warned {
+        return Err(Box::new(EdCommandError::BufferModified));
+    }
+
+    let file = read_file_arg(buffer, command)?;
+    if let Some(filename) = filename_arg(command)?.filter(|arg| !arg.starts_with('!')) {
+        buffer.filename = Some(filename.to_string());
+    }
+
+    print_byte_count(buffer, file.byte_count);
+    buffer.replace_contents(file.lines);
+    buffer.line_ending = file.line_ending;
+    buffer.missing_newline = file.missing_newline;
+    append_missing_newline(buffer);
+    Ok(REPLStatus::Continue)
+}
+
+/// Reads a file, or the output of a shell command when the argument starts
+/// with `!`, into the buffer after the addressed line and prints the number
+/// of bytes read.
+///
+/// With no argument the default file is read. A filename becomes the
+/// default filename if none is set.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which the lines are added to.
+/// * `command` - A reference to the `EdCommand`, containing the address and the filename or `!command` argument.
+///
+/// # Return Value
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)` if the lines were read,
+/// `EdCommandError::NoCurrentFilename` if no file is named or set, or the error from reading the file or
+/// running the command.
+fn read(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let file = read_file_arg(buffer, command)?;
+    if let Some(filename) = filename_arg(command)?.filter(|arg| !arg.starts_with('!')) {
+        if buffer.filename.is_none() {
+            buffer.filename = Some(filename.to_string());
+        }
+    }
+
+    print_byte_count(buffer, file.byte_count);
+    // lines read into the buffer always get a newline
+    if file.missing_newline && !buffer.script_mode {
+        println!("Newline appended");
+    }
+    if !file.lines.is_empty() {
+        append_into_buffer(buffer, &command.address2, file.lines)?;
+    }
+    Ok(REPLStatus::Continue)
+}
+
+/// Shows or sets the line ending written after each line when the buffer is
+/// saved. With no argument the current style, `lf` or `crlf`, is printed.
+/// Changing the style marks the buffer as modified.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which keeps the line ending style.
+/// * `command` - A reference to the `EdCommand`, whose optional argument is `lf` or `crlf`.
+///
+/// # Return Value
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)`, or
+/// `EdCommandError::InvalidCommandSuffix` for any other argument.
+fn line_endings(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let line_ending = match filename_arg(command)? {
+        None => {
+            match buffer.line_ending {
+                LineEnding::Lf => println!("lf"),
+                LineEnding::CrLf => println!("crlf"),
+            }
+            return Ok(REPLStatus::Continue);
+        }
+        Some("lf") => LineEnding::Lf,
+        Some("crlf") => LineEnding::CrLf,
+        Some(_) => return Err(Box::new(EdCommandError::InvalidCommandSuffix)),
+    };
+    if buffer.line_ending != line_ending {
+        buffer.line_ending = line_ending;
+        buffer.modified = true;
+    }
+    Ok(REPLStatus::Continue)
+}
+
+/// Reads the lines named by the argument of `e` or `r`: the output of a
+/// shell command when it starts with `!`, otherwise the named file or the
+/// default file. Returns the lines with the line ending style they were
+/// read with.
+fn read_file_arg(buffer: &LineBuffer, command: &EdCommand) -> Result<FileLines, Box<dyn Error>> {
+    let filename = match filename_arg(command)? {
+        Some(shell_command) if shell_command.starts_with('!') => {
+            let lines = command_output(&shell_command[1..])?;
+            return Ok(FileLines {
+                byte_count: byte_count(&lines, LineEnding::Lf),
+                lines,
+                line_ending: LineEnding::Lf,
+                missing_newline: false,
+            });
+        }
+        Some(filename) => filename,
+        None => buffer
+            .filename
+            .as_deref()
+            .ok_or(EdCommandError::NoCurrentFilename)?,
+    };
+    let file = read_lines(filename, buffer.strip_trailing_cr)
+        .map_err(EdCommandError::CannotOpenInputFile)?;
+    Ok(file)
+}
+
+/// Adds the newline missing from the end of a newly loaded file, so it is
+/// written with one, and prints `Newline appended`. Nothing changes when
+/// the buffer keeps a missing newline, and nothing is printed in script mode.
+pub fn append_missing_newline(buffer: &mut LineBuffer) {
+    if buffer.missing_newline && !buffer.keep_missing_newline {
+        buffer.missing_newline = false;
+        if !buffer.script_mode {
+            println!("Newline appended");
+        }
+    }
+}
+
+/// Prints the number of bytes read or written by a file command. Nothing is
+/// printed in script mode.
+pub fn print_byte_count(buffer: &LineBuffer, count: usize) {
+    if !buffer.script_mode {
+        println!("{}", count);
+    }
+}
+
+/// Runs a shell command, printing `!` when it finishes, except in script
+/// mode.
+///
+/// `%` in the command is replaced by the default filename (`\%` is a
+/// literal `%`), and a leading `!` by the previous command. When either
+/// expansion happens the expanded command is printed before it runs.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which keeps the default filename and the previous command.
+/// * `command` - A reference to the `EdCommand`, whose argument is the command text.
+///
+/// # Return Value
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)` once the command has run,
+/// an error from `expand_command`, or an error if the shell could not be started.
+fn shell_escape(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let shell_command = shell_command_arg(buffer, command)?;
+    run_shell(&shell_command)?;
+    if !buffer.script_mode {
+        println!("!");
+    }
+    Ok(REPLStatus::Continue)
+}
+
+/// Replaces the addressed lines with the output of a shell command that is
+/// given them as input, as `(.,.)!command` or `(.,.)|command`. The command is
+/// expanded as for `!`, and the change is undone as one step.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, whose addressed lines are filtered.
+/// * `command` - A reference to the `EdCommand`, containing the range and the command text.
+///
+/// # Return Value
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>` with `Ok(REPLStatus::Continue)` once the lines are replaced,
+/// `EdCommandError::InvalidRange` for line 0, `EdCommandError::EmptyBuffer` if there are no lines,
+/// an error from `expand_command`, or an error if the shell could not be started.
+fn filter(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    if buffer.len() == 0 {
+        return Err(Box::new(EdCommandError::EmptyBuffer));
+    }
+    if command.address1 == Address::Absolute(0) {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+    let shell_command = shell_command_arg(buffer, command)?;
+    let start = address_to_index(command.address1.clone(), buffer)?;
+    let end = address_to_index(command.address2.clone(), buffer)?;
+    let input = buffer.lines.as_deref().unwrap_or_default()[start..=end].to_vec();
+    let output = filter_lines(&shell_command, input)?;
+    correct_into_buffer(buffer, &command.address1, &command.address2, output)?;
+    Ok(REPLStatus::Continue)
+}
+
+/// Expands the command text of `!` or `|`, printing it when it changed, and
+/// saves it as the previous shell command.
+fn shell_command_arg(buffer: &mut LineBuffer, command: &EdCommand) -> Result<String, EdCommandError> {
+    let text = command.text_args()?.unwrap_or("");
+    let (shell_command, expanded) = expand_command(
+        text,
+        buffer.last_shell_command.as_deref(),
+        buffer.filename.as_deref(),
+    )?;
+    if expanded {
+        println!("{}", shell_command);
     }
+    buffer.last_shell_command = Some(shell_command.clone());
+    Ok(shell_command)
 }
 
-pub fn address_to_index(address: Address, buffer: &LineBuffer) -> usize {
+/// Converts an address to the zero-based index of its line in the buffer.
+///
+/// Addresses past the end of the buffer give the index of the last line, and
+/// address `0` gives index `0`; commands that do not accept line 0 reject it
+/// before converting.
+///
+/// # Arguments
+///
+/// * `address` - The `Address` to convert, usually already resolved by `validate_range`.
+/// * `buffer` - A reference to the `LineBuffer` the address refers to.
+///
+/// # Return Value
+///
+/// Returns `Result<usize, EdCommandError>` with the index, or the error from `resolve_address`
+/// if a relative, search or mark address cannot be resolved.
+pub fn address_to_index(address: Address, buffer: &LineBuffer) -> Result<usize, EdCommandError> {
     let index = match address {
         Address::Absolute(addr) => addr,
         Address::Last => buffer.len(),
         Address::Current => buffer.current_line,
-
-        _ => buffer.current_line,
+        _ => resolve_address(&address, buffer)?,
     };
 
     if index >= buffer.len() {
-        buffer.len().saturating_sub(1)
+        Ok(buffer.len().saturating_sub(1))
     } else {
-        index.saturating_sub(1)
+        Ok(index.saturating_sub(1))
     }
 }
 
-/// Prints the lines within the specified range in the buffer.
+/// Prints the lines within the specified range in the buffer. An `n` or
+/// `l` suffix prints them with line numbers or unambiguously.
 ///
 /// # Arguments
 ///
@@ -181,30 +718,30 @@ pub fn address_to_index(address: Address, buffer: &LineBuffer) -> usize {
 /// # Result
 ///
 /// Returns `Result<REPLStatus, Box<dyn Error>>`, where `Ok(REPLStatus::Continue)` indicates successful execution.
-/// Returns an `EdCommandError::EmptyBuffer` error if the buffer is empty, or an `EdCommandError::InvalidRange` error if the specified range is invalid.
+/// Returns an `EdCommandError::EmptyBuffer` error if the buffer is empty, an `EdCommandError::InvalidRange` error if the
+/// specified range is invalid, or an `EdCommandError::InvalidCommandSuffix` error for an invalid suffix.
 fn print(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
     if buffer.len() == 0 {
         return Err(Box::new(EdCommandError::EmptyBuffer));
     }
-    let low = address_to_index(command.address1.clone(), buffer);
-    let high = address_to_index(command.address2.clone(), buffer);
+    if command.address1 == Address::Absolute(0) {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+    let low = address_to_index(command.address1.clone(), buffer)?;
+    let high = address_to_index(command.address2.clone(), buffer)?;
     if low > high {
         return Err(Box::new(EdCommandError::InvalidRange));
     }
-    match &buffer.lines {
-        Some(lines) => {
-            for i in low..=high {
-                println!("{}", lines[i])
-            }
-        }
-        None => println!(""),
-    };
+    print_lines(buffer, low, high, PrintSuffix::Print, suffix);
+    set_current_line_number(buffer, command)?;
     Ok(REPLStatus::Continue)
 }
 
 /// Prints the lines within the specified range from the `LineBuffer`
-/// each line prefixed with its line number.
-/// If the buffer is empty or the specified range is invalid,
+/// each line prefixed with its line number. An `l` suffix also prints them
+/// unambiguously.
+/// If the buffer is empty or the specified range or suffix is invalid,
 /// it returns an appropriate error.
 ///
 /// # Arguments
@@ -219,29 +756,239 @@ fn print_with_numbers(
     buffer: &LineBuffer,
     command: &EdCommand,
 ) -> Result<REPLStatus, Box<dyn Error>> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
     if buffer.len() == 0 {
         return Err(Box::new(EdCommandError::EmptyBuffer));
     }
-    let low = address_to_index(command.address1.clone(), buffer);
-    let high = address_to_index(command.address2.clone(), buffer);
+    if command.address1 == Address::Absolute(0) {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+    let low = address_to_index(command.address1.clone(), buffer)?;
+    let high = address_to_index(command.address2.clone(), buffer)?;
     if low > high {
         return Err(Box::new(EdCommandError::InvalidRange));
     }
-    match &buffer.lines {
-        Some(lines) => {
-            for i in low..=high {
-                println!("{:>4}\t{}", i + 1, lines[i]);
-            }
+    print_lines(buffer, low, high, PrintSuffix::Number, suffix);
+    Ok(REPLStatus::Continue)
+}
+
+/// Prints the lines within the specified range unambiguously, showing
+/// control characters as escape sequences and the end of each line as `$`.
+/// Long lines are folded with a trailing `\`. An `n` suffix also prints line
+/// numbers.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which holds the lines of text being edited.
+/// * `command` - A reference to the `EdCommand`, containing the addresses specifying the range of lines to list.
+///
+/// # Returns
+///
+/// * `Result<REPLStatus, Box<dyn Error>>` - Returns `Ok(REPLStatus::Continue)` on success, otherwise an error wrapped in a `Box<dyn Error>`.
+fn list(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if buffer.len() == 0 {
+        return Err(Box::new(EdCommandError::EmptyBuffer));
+    }
+    if command.address1 == Address::Absolute(0) {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+    let low = address_to_index(command.address1.clone(), buffer)?;
+    let high = address_to_index(command.address2.clone(), buffer)?;
+    if low > high {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+    print_lines(buffer, low, high, PrintSuffix::List, suffix);
+    set_current_line_number(buffer, command)?;
+    Ok(REPLStatus::Continue)
+}
+
+/// Prints a window of lines starting at the addressed line and moves to the
+/// last line printed.
+///
+/// The argument is an optional window size, remembered for later `z`
+/// commands, followed by an optional `p`, `n` or `l` suffix that sets how the
+/// lines are printed.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which holds the lines and the window size.
+/// * `command` - A reference to the `EdCommand`, containing the first line to print and the window size argument.
+///
+/// # Returns
+///
+/// * `Result<REPLStatus, Box<dyn Error>>` - Returns `Ok(REPLStatus::Continue)` on success, `EdCommandError::InvalidRange`
+///   for line 0, or `EdCommandError::InvalidCommandSuffix` for a window size of 0 or an unknown suffix.
+fn scroll(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let first = resolve_address(&command.address2, buffer)?;
+    if first == 0 {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+
+    let args = command.text_args()?.unwrap_or("");
+    let digits = args.len() - args.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let suffix = parse_print_suffix(Some(&args.as_bytes()[digits..]))?.unwrap_or(PrintSuffix::Print);
+    if digits > 0 {
+        match args[..digits].parse() {
+            Ok(0) | Err(_) => return Err(Box::new(EdCommandError::InvalidCommandSuffix)),
+            Ok(lines) => buffer.window_lines = lines,
         }
-        None => println!(""),
+    }
+
+    let last = first.saturating_add(buffer.window_lines - 1).min(buffer.len());
+    if let Some(lines) = &buffer.lines {
+        for (i, line) in lines.iter().enumerate().take(last).skip(first - 1) {
+            print_line(line, i + 1, suffix);
+        }
+    }
+    buffer.current_line = last;
+    Ok(REPLStatus::Continue)
+}
+
+/// Moves to the last addressed line and prints it. Used when a line
+/// contains only an address.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which holds the lines of text being edited.
+/// * `command` - A reference to the `EdCommand`, containing the address to move to.
+///
+/// # Result
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>`, where `Ok(REPLStatus::Continue)` indicates successful execution.
+fn goto_line(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let command = EdCommand {
+        address1: command.address2.clone(),
+        ..command.clone()
     };
+    print(buffer, &command)
+}
+
+/// Marks the addressed line with a lowercase letter so it can later be
+/// addressed as `'x`. The mark moves with the line as the buffer changes.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer`, which holds the marks.
+/// * `command` - A reference to the `EdCommand`, containing the address and the mark letter as its argument.
+///
+/// # Result
+///
+/// Returns `Result<REPLStatus, Box<dyn Error>>`, where `Ok(REPLStatus::Continue)` indicates successful execution.
+/// Returns an `EdCommandError::InvalidMarkCharacter` error if the argument is not a single lowercase letter,
+/// or an `EdCommandError::InvalidRange` error if the address is line 0.
+fn mark(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, Box<dyn Error>> {
+    let mut chars = command.text_args()?.unwrap_or("").chars();
+    let name = match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_lowercase() => c,
+        _ => return Err(Box::new(EdCommandError::InvalidMarkCharacter)),
+    };
+    let line = resolve_address(&command.address2, buffer)?;
+    if line == 0 {
+        return Err(Box::new(EdCommandError::InvalidRange));
+    }
+    buffer.marks.insert(name, line);
     Ok(REPLStatus::Continue)
 }
 
+/// Parses the optional `p`, `n` or `l` print suffix given as a command's arguments.
+///
+/// # Returns
+///
+/// * `Result<Option<PrintSuffix>, EdCommandError>` - The suffix, `None` if there are no arguments,
+///   or `EdCommandError::InvalidCommandSuffix` for anything else.
+pub fn parse_print_suffix(args: Option<&[u8]>) -> Result<Option<PrintSuffix>, EdCommandError> {
+    match args {
+        None | Some(b"") => Ok(None),
+        Some(b"p") => Ok(Some(PrintSuffix::Print)),
+        Some(b"n") => Ok(Some(PrintSuffix::Number)),
+        Some(b"l") => Ok(Some(PrintSuffix::List)),
+        Some(_) => Err(EdCommandError::InvalidCommandSuffix),
+    }
+}
+
+/// Prints the current line as requested by a `p`, `n` or `l` command suffix.
+/// Does nothing when there is no suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A reference to the `LineBuffer`, which holds the lines of text being edited.
+/// * `suffix` - The optional `PrintSuffix` given with the command.
+pub fn print_suffix(buffer: &LineBuffer, suffix: Option<PrintSuffix>) {
+    let index = buffer.current_line.saturating_sub(1);
+    let line = match &buffer.lines {
+        Some(lines) if index < lines.len() => &lines[index],
+        _ => return,
+    };
+    if let Some(suffix) = suffix {
+        print_line(line, index + 1, suffix);
+    }
+}
+
+/// Prints one line in the style of the `p`, `n` or `l` command. `p` and `n`
+/// write the bytes of the line unchanged, even when they are not UTF-8.
+///
+/// # Arguments
+///
+/// * `line` - The bytes of the line.
+/// * `number` - The one-indexed line number, shown by `n`.
+/// * `suffix` - The `PrintSuffix` naming the style.
+fn print_line(line: &[u8], number: usize, suffix: PrintSuffix) {
+    print_styled(
+        line,
+        number,
+        suffix == PrintSuffix::Number,
+        suffix == PrintSuffix::List,
+    );
+}
+
+/// Prints one line, prefixed with its line number when `numbered` is set,
+/// and unambiguously when `listed` is set.
+fn print_styled(line: &[u8], number: usize, numbered: bool, listed: bool) {
+    if numbered {
+        print!("{:>4}\t", number);
+    }
+    if listed {
+        for row in list_line(line, LIST_WIDTH) {
+            println!("{}", row);
+        }
+    } else {
+        print_bytes(line);
+    }
+}
+
+/// Prints the zero-indexed lines `low..=high` for the `p`, `n` or `l`
+/// command given as `style`. A print suffix adds its style, so `nl` prints
+/// line numbers and lists the lines.
+fn print_lines(
+    buffer: &LineBuffer,
+    low: usize,
+    high: usize,
+    style: PrintSuffix,
+    suffix: Option<PrintSuffix>,
+) {
+    let styles = [Some(style), suffix];
+    let numbered = styles.contains(&Some(PrintSuffix::Number));
+    let listed = styles.contains(&Some(PrintSuffix::List));
+    if let Some(lines) = &buffer.lines {
+        for (i, line) in lines.iter().enumerate().take(high + 1).skip(low) {
+            print_styled(line, i + 1, numbered, listed);
+        }
+    }
+}
+
+/// Writes the bytes of a line and a newline to standard output.
+fn print_bytes(line: &[u8]) {
+    let mut stdout = std::io::stdout().lock();
+    // a closed standard output is not an error in the command
+    let _ = stdout.write_all(line).and_then(|_| stdout.write_all(b"\n"));
+}
+
 /// Sets the line number from the `command` on the `buffer` object.
-fn set_current_line_number(buffer: &mut LineBuffer, command: &EdCommand) {
-    let index = address_to_index(command.address2.clone(), buffer);
+fn set_current_line_number(buffer: &mut LineBuffer, command: &EdCommand) -> Result<(), EdCommandError> {
+    let index = address_to_index(command.address2.clone(), buffer)?;
     buffer.current_line = index + 1;
+    Ok(())
 }
 
 /// Prints the current line number of the buffer.
@@ -258,17 +1005,19 @@ fn print_current_line_number(
     buffer: &mut LineBuffer,
     command: &EdCommand,
 ) -> Result<REPLStatus, Box<dyn Error>> {
-    set_current_line_number(buffer, &command);
+    set_current_line_number(buffer, command)?;
     println!("{}", buffer.current_line);
     Ok(REPLStatus::Continue)
 }
 
 #[cfg(test)]
-
 mod tests {
 
     use super::*;
+    use crate::buffer::line_array_buffer::{run, text_lines};
+    use crate::ed_command_parser::Rule;
     use rstest::rstest;
+    use std::collections::VecDeque;
 
     #[test]
     fn quit_returns_quit_signal() {
@@ -278,11 +1027,82 @@ mod tests {
             ..EdCommand::default()
         };
 
-        if let Ok(out) = command_runner(&mut buffer, &command) {
-            assert_eq!(out, REPLStatus::Quit);
-        } else {
-            assert!(false)
-        }
+        let out = command_runner(&mut buffer, &command, &mut VecDeque::new()).expect("quit failed");
+        assert_eq!(out, REPLStatus::Quit);
+    }
+
+    #[test]
+    fn quit_warns_once_when_modified() {
+        let mut buffer = LineBuffer {
+            modified: true,
+            ..LineBuffer::empty()
+        };
+
+        assert!(run(&mut buffer, "q").is_err());
+        assert_eq!(run(&mut buffer, "q").expect("second quit failed"), REPLStatus::Quit);
+
+        run(&mut buffer, "=").expect("= failed");
+        assert!(run(&mut buffer, "q").is_err(), "The warning should be given again after another command");
+        assert_eq!(run(&mut buffer, "Q").expect("Q failed"), REPLStatus::Quit);
+    }
+
+    #[test]
+    fn quit_after_write_does_not_warn() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "1d").expect("delete failed");
+        run(&mut buffer, "w /tmp/ed_quit_after_write.txt").expect("write failed");
+        assert_eq!(run(&mut buffer, "q").expect("quit failed"), REPLStatus::Quit);
+    }
+
+    #[rstest]
+    #[case("1q", "Unexpected address")]
+    #[case("2,3u", "Unexpected address")]
+    #[case("qx", "Invalid command suffix")]
+    #[case("b", "Unknown command")]
+    #[case("2b", "Unknown command")]
+    #[case("9p", "Invalid address")]
+    #[case("r /nonexistent/file", "Cannot open input file")]
+    #[case("w /nonexistent/file", "Cannot open output file")]
+    #[case("//p", "No previous pattern")]
+    #[case("1s", "No previous substitution")]
+    #[case("1s a b ", "Invalid pattern delimiter")]
+    #[case("1s/o", "Missing pattern delimiter")]
+    #[case("1s/o/0/x", "Invalid command suffix")]
+    #[case("pz", "Invalid command suffix")]
+    #[case("1s/(/x/", "Unclosed group")]
+    #[case("/[a/", "Unclosed character class")]
+    fn rstest_error_messages(#[case] line: &str, #[case] expected: &str) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let error = run(&mut buffer, line).expect_err("command should fail");
+        assert_eq!(error.to_string(), expected);
+    }
+
+    #[rstest]
+    #[case(vec![Rule::address], "Invalid address")]
+    #[case(vec![Rule::mark], "Invalid mark character")]
+    #[case(vec![Rule::forward_pattern], "Missing pattern delimiter")]
+    #[case(vec![Rule::command, Rule::transfer], "Unknown command")]
+    #[case(vec![Rule::EOI], "Invalid command suffix")]
+    fn rstest_parse_error_messages(#[case] positives: Vec<Rule>, #[case] expected: &str) {
+        let variant = pest::error::ErrorVariant::ParsingError { positives, negatives: vec![] };
+        let position = pest::Position::from_start("");
+        let error = EdCommandError::ParseError(Box::new(pest::error::Error::new_from_pos(variant, position)));
+        assert_eq!(error.to_string(), expected);
+    }
+
+    #[test]
+    fn test_error_explanations() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let error = run(&mut buffer, "9p").expect_err("command should fail");
+        report_error(&mut buffer, error.as_ref());
+        assert_eq!(buffer.last_error.as_deref(), Some("Invalid address"));
+
+        run(&mut buffer, "h").expect("h failed");
+        assert!(!buffer.explain_errors);
+        run(&mut buffer, "H").expect("H failed");
+        assert!(buffer.explain_errors);
+        run(&mut buffer, "H").expect("H failed");
+        assert!(!buffer.explain_errors);
     }
 
     #[test]
@@ -296,18 +1116,12 @@ mod tests {
             ..EdCommand::default()
         };
 
-        match command_runner(&mut buffer, &command) {
+        match command_runner(&mut buffer, &command, &mut VecDeque::new()) {
             Ok(out) => assert_eq!(out, REPLStatus::Continue),
-            Err(e) => {
-                println!("{:?}", e);
-                assert!(false);
-            }
-        }
-        if let Ok(out) = command_runner(&mut buffer, &command) {
-            assert_eq!(out, REPLStatus::Continue);
-        } else {
-            assert!(false)
+            Err(e) => panic!("{:?}", e),
         }
+        let out = command_runner(&mut buffer, &command, &mut VecDeque::new()).expect("second write failed");
+        assert_eq!(out, REPLStatus::Continue);
     }
     #[test]
     fn write_with_no_filename() {
@@ -321,12 +1135,9 @@ mod tests {
             ..EdCommand::default()
         };
 
-        match command_runner(&mut buffer, &command) {
+        match command_runner(&mut buffer, &command, &mut VecDeque::new()) {
             Ok(out) => assert_eq!(out, REPLStatus::Continue),
-            Err(e) => {
-                println!("{:?}", e);
-                assert!(true);
-            }
+            Err(e) => println!("{:?}", e),
         }
     }
     #[test]
@@ -340,12 +1151,9 @@ mod tests {
             ..EdCommand::default()
         };
 
-        match command_runner(&mut buffer, &command) {
+        match command_runner(&mut buffer, &command, &mut VecDeque::new()) {
             Ok(out) => assert_eq!(out, REPLStatus::Quit),
-            Err(e) => {
-                println!("{:?}", e);
-                assert!(false);
-            }
+            Err(e) => panic!("{:?}", e),
         }
     }
     #[test]
@@ -360,15 +1168,42 @@ mod tests {
             ..EdCommand::default()
         };
 
-        match command_runner(&mut buffer, &command) {
+        match command_runner(&mut buffer, &command, &mut VecDeque::new()) {
             Ok(out) => assert_eq!(out, REPLStatus::Continue),
-            Err(e) => {
-                println!("{:?}", e);
-                assert!(true);
-            }
+            Err(e) => println!("{:?}", e),
         }
     }
 
+    #[rstest]
+    #[case("2,3w ", "/tmp/ed_write_part.txt", vec!["two", "three"], true)]
+    #[case("w ", "/tmp/ed_write_all.txt", vec!["one", "two", "three", "four", "five"], false)]
+    #[case("$w !cat > ", "/tmp/ed_write_pipe.txt", vec!["five"], true)]
+    fn rstest_write_range(
+        #[case] line: &str,
+        #[case] out_filename: &str,
+        #[case] expected: Vec<&str>,
+        #[case] modified: bool,
+    ) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.modified = true;
+        run(&mut buffer, &format!("{}{}", line, out_filename)).expect("write failed");
+        assert_eq!(read_lines(out_filename, false).unwrap().lines, text_lines(&expected));
+        assert_eq!(buffer.modified, modified);
+    }
+
+    #[test]
+    fn test_write_append() {
+        let out_filename = "/tmp/ed_write_append.txt";
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, &format!("1w {}", out_filename)).expect("write failed");
+        run(&mut buffer, &format!("4,5W {}", out_filename)).expect("append failed");
+        assert_eq!(
+            read_lines(out_filename, false).unwrap().lines,
+            text_lines(&["one", "four", "five"])
+        );
+        assert!(run(&mut buffer, &format!("0W {}", out_filename)).is_err());
+    }
+
     #[rstest]
     #[case(Address::Absolute(5), 4)]
     #[case(Address::Absolute(1000), 4)]
@@ -378,7 +1213,7 @@ mod tests {
     fn rstest_address_to_index(#[case] address: Address, #[case] expected: usize) {
         let filename = "test_files/one.txt";
         let buffer = LineBuffer::from_file(filename).unwrap();
-        let actual_index = address_to_index(address, &buffer);
+        let actual_index = address_to_index(address, &buffer).unwrap();
         assert_eq!(actual_index, expected);
     }
 
@@ -390,10 +1225,395 @@ mod tests {
         let filename = "test_files/one.txt";
         let mut buffer = LineBuffer::from_file(filename).unwrap();
         buffer.current_line = current;
-        let actual_index = address_to_index(Address::Current, &buffer);
+        let actual_index = address_to_index(Address::Current, &buffer).unwrap();
         assert_eq!(actual_index, expected);
     }
 
+    #[rstest]
+    #[case(Address::Offset(Box::new(Address::Current), 2), 5)]
+    #[case(Address::Offset(Box::new(Address::Current), -3), 0)]
+    #[case(Address::Offset(Box::new(Address::Last), -2), 3)]
+    #[case(Address::Offset(Box::new(Address::Absolute(1)), 3), 4)]
+    fn rstest_resolve_offset(#[case] address: Address, #[case] expected: usize) {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        buffer.current_line = 3;
+        assert_eq!(resolve_address(&address, &buffer).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(Address::Offset(Box::new(Address::Current), 3))]
+    #[case(Address::Offset(Box::new(Address::Current), -4))]
+    #[case(Address::Offset(Box::new(Address::Last), 1))]
+    fn rstest_resolve_offset_out_of_range(#[case] address: Address) {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        buffer.current_line = 3;
+        assert!(matches!(
+            resolve_address(&address, &buffer),
+            Err(EdCommandError::InvalidRange)
+        ));
+    }
+
+    #[rstest]
+    #[case(Address::Offset(Box::new(Address::Last), 1))]
+    #[case(Address::Mark('z'))]
+    fn rstest_address_to_index_unresolved(#[case] address: Address) {
+        let filename = "test_files/one.txt";
+        let buffer = LineBuffer::from_file(filename).unwrap();
+        assert!(address_to_index(address, &buffer).is_err());
+    }
+
+    #[rstest]
+    #[case(5, "$-5p")]
+    #[case(1, "-p")]
+    #[case(1, "0p")]
+    #[case(1, "0n")]
+    #[case(1, "0l")]
+    #[case(1, "0")]
+    #[case(3, "0,2p")]
+    fn rstest_print_line_zero(#[case] current: usize, #[case] line: &str) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.current_line = current;
+        let error = run(&mut buffer, line).expect_err("line 0 should not print");
+        assert!(matches!(error.downcast_ref(), Some(EdCommandError::InvalidRange)));
+        assert_eq!(buffer.current_line, current);
+    }
+
+    #[test]
+    fn test_validate_range_semicolon_moves_current() {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        buffer.current_line = 1;
+        let command = EdCommand {
+            address1: Address::Absolute(2),
+            range_sep: RangeSep::Semicolon,
+            address2: Address::Offset(Box::new(Address::Current), 2),
+            ..EdCommand::default()
+        };
+        let resolved = validate_range(&mut buffer, &command).unwrap();
+        assert_eq!(resolved.address1, Address::Absolute(2));
+        assert_eq!(resolved.address2, Address::Absolute(4));
+    }
+
+    #[test]
+    fn test_mark_then_address() {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        let command = EdCommand {
+            address1: Address::Absolute(4),
+            address2: Address::Absolute(4),
+            command: Some("k".to_string()),
+            command_args: Some(b"q".to_vec()),
+            ..EdCommand::default()
+        };
+        command_runner(&mut buffer, &command, &mut VecDeque::new()).expect("mark failed");
+        assert_eq!(resolve_address(&Address::Mark('q'), &buffer).unwrap(), 4);
+        assert!(matches!(
+            resolve_address(&Address::Mark('r'), &buffer),
+            Err(EdCommandError::UnknownMark)
+        ));
+    }
+
+    #[rstest]
+    #[case(None)]
+    #[case(Some("A"))]
+    #[case(Some("ab"))]
+    fn rstest_mark_rejects_bad_name(#[case] args: Option<&str>) {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        let command = EdCommand {
+            address1: Address::Absolute(1),
+            address2: Address::Absolute(1),
+            command: Some("k".to_string()),
+            command_args: args.map(|args| args.as_bytes().to_vec()),
+            ..EdCommand::default()
+        };
+        assert!(command_runner(&mut buffer, &command, &mut VecDeque::new()).is_err());
+    }
+
+    #[test]
+    fn test_edit_file_sets_filename() {
+        let mut buffer = LineBuffer::empty();
+        run(&mut buffer, "e test_files/one.txt").expect("edit failed");
+        assert_eq!(buffer.filename.as_deref(), Some("test_files/one.txt"));
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.current_line, 5);
+        assert!(!buffer.modified);
+    }
+
+    #[test]
+    fn test_edit_refuses_once_when_modified() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "1d").unwrap();
+        run(&mut buffer, "1ka").unwrap();
+
+        let first = run(&mut buffer, "e").unwrap_err();
+        assert_eq!(format!("{}", first), format!("{}", EdCommandError::BufferModified));
+        assert_eq!(buffer.len(), 4);
+
+        run(&mut buffer, "e").expect("second edit failed");
+        assert_eq!(buffer.len(), 5);
+        assert!(buffer.marks.is_empty());
+        assert!(run(&mut buffer, "u").is_err());
+    }
+
+    #[test]
+    fn test_edit_warning_resets_after_other_command() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "1d").unwrap();
+        assert!(run(&mut buffer, "e").is_err());
+        run(&mut buffer, "1p").unwrap();
+        assert!(run(&mut buffer, "e").is_err());
+        assert_eq!(buffer.len(), 4);
+    }
+
+    #[test]
+    fn test_force_edit_and_shell_command() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "1d").unwrap();
+        run(&mut buffer, "E !printf 'a\\nb\\n'").expect("edit failed");
+        assert_eq!(buffer.lines, Some(text_lines(&["a", "b"])));
+        assert_eq!(buffer.filename.as_deref(), Some("test_files/one.txt"));
+    }
+
+    #[rstest]
+    #[case("r test_files/one.txt", vec!["a", "b", "one", "two", "three", "four", "five"], 7)]
+    #[case("0r test_files/one.txt", vec!["one", "two", "three", "four", "five", "a", "b"], 5)]
+    #[case("1r !echo x", vec!["a", "x", "b"], 2)]
+    fn rstest_read(#[case] line: &str, #[case] expected: Vec<&str>, #[case] current: usize) {
+        let mut buffer = LineBuffer {
+            lines: Some(text_lines(&["a", "b"])),
+            ..LineBuffer::empty()
+        };
+        run(&mut buffer, line).expect("read failed");
+        assert_eq!(buffer.lines.unwrap(), text_lines(&expected));
+        assert_eq!(buffer.current_line, current);
+    }
+
+    #[test]
+    fn test_read_sets_filename_only_when_unset() {
+        let mut buffer = LineBuffer::empty();
+        run(&mut buffer, "r test_files/one.txt").expect("read failed");
+        assert_eq!(buffer.filename.as_deref(), Some("test_files/one.txt"));
+        assert!(buffer.modified);
+
+        let mut buffer = LineBuffer {
+            filename: Some("other.txt".to_string()),
+            ..LineBuffer::empty()
+        };
+        run(&mut buffer, "r test_files/one.txt").expect("read failed");
+        assert_eq!(buffer.filename.as_deref(), Some("other.txt"));
+    }
+
+    #[rstest]
+    #[case("z2", 3, 2)]
+    #[case("1z", 5, 22)]
+    #[case("4z10n", 5, 10)]
+    #[case("2z1l", 2, 1)]
+    #[case("1z18446744073709551615", 5, usize::MAX)]
+    fn rstest_scroll(#[case] line: &str, #[case] current: usize, #[case] window_lines: usize) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.current_line = 1;
+        run(&mut buffer, line).expect("scroll failed");
+        assert_eq!(buffer.current_line, current);
+        assert_eq!(buffer.window_lines, window_lines);
+    }
+
+    #[rstest]
+    #[case("z0")]
+    #[case("zq")]
+    #[case("0z")]
+    #[case("z")]
+    fn rstest_scroll_errors(#[case] line: &str) {
+        // on the last line, `z` would start past the end of the buffer
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.current_line = 5;
+        assert!(run(&mut buffer, line).is_err());
+    }
+
+    #[test]
+    fn test_shell_escape_remembers_command() {
+        let mut buffer = LineBuffer {
+            filename: Some("test_files/one.txt".to_string()),
+            ..LineBuffer::empty()
+        };
+        assert!(run(&mut buffer, "!!").is_err());
+        run(&mut buffer, "!test -f %").expect("shell escape failed");
+        assert_eq!(buffer.last_shell_command.as_deref(), Some("test -f test_files/one.txt"));
+        run(&mut buffer, "!! && true").expect("repeat failed");
+        assert_eq!(
+            buffer.last_shell_command.as_deref(),
+            Some("test -f test_files/one.txt && true")
+        );
+    }
+
+    #[rstest]
+    #[case("2,4!sort", vec!["one", "four", "three", "two", "five"], 4)]
+    #[case("2,4|sort", vec!["one", "four", "three", "two", "five"], 4)]
+    #[case("|tr a-z A-Z", vec!["one", "two", "THREE", "four", "five"], 3)]
+    #[case("1,2!true", vec!["three", "four", "five"], 0)]
+    #[case(",!sed 1q", vec!["one"], 1)]
+    fn rstest_filter(#[case] line: &str, #[case] expected: Vec<&str>, #[case] current: usize) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.current_line = 3;
+        run(&mut buffer, line).expect("filter failed");
+        assert_eq!(buffer.lines.clone().unwrap(), text_lines(&expected));
+        assert_eq!(buffer.current_line, current);
+
+        run(&mut buffer, "u").expect("undo failed");
+        assert_eq!(
+            buffer.lines.unwrap(),
+            text_lines(&["one", "two", "three", "four", "five"])
+        );
+    }
+
+    #[rstest]
+    #[case("!ls", "Shell access restricted")]
+    #[case("1,2!sort", "Shell access restricted")]
+    #[case("|sort", "Shell access restricted")]
+    #[case("r !ls", "Shell access restricted")]
+    #[case("w !cat", "Shell access restricted")]
+    #[case("e !ls", "Shell access restricted")]
+    #[case("r /etc/passwd", "Directory access restricted")]
+    #[case("w ../out.txt", "Directory access restricted")]
+    #[case("E ..", "Directory access restricted")]
+    #[case("wq sub/out.txt", "Directory access restricted")]
+    fn rstest_restricted(#[case] line: &str, #[case] expected: &str) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.restricted = true;
+        let error = run(&mut buffer, line).expect_err("command should be refused");
+        assert_eq!(error.to_string(), expected);
+        assert_eq!(buffer.len(), 5);
+    }
+
+    #[test]
+    fn test_restricted_allows_local_files() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        buffer.restricted = true;
+        run(&mut buffer, "1p").expect("print failed");
+        assert!(check_filename("notes.txt").is_ok());
+        assert!(check_filename("test_files/one.txt").is_err());
+    }
+
+    #[test]
+    fn test_line_endings_convert() {
+        let out_filename = "/tmp/ed_line_endings.txt";
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "o crlf").expect("o failed");
+        assert_eq!(buffer.line_ending, LineEnding::CrLf);
+        assert!(buffer.modified);
+        run(&mut buffer, &format!("1,2w {}", out_filename)).expect("write failed");
+        assert_eq!(std::fs::read_to_string(out_filename).unwrap(), "one\r\ntwo\r\n");
+
+        run(&mut buffer, &format!("E {}", out_filename)).expect("edit failed");
+        assert_eq!(buffer.line_ending, LineEnding::CrLf);
+        assert_eq!(buffer.lines.clone().unwrap(), text_lines(&["one", "two"]));
+        run(&mut buffer, "o lf").expect("o failed");
+        run(&mut buffer, "w").expect("write failed");
+        assert_eq!(std::fs::read_to_string(out_filename).unwrap(), "one\ntwo\n");
+
+        assert!(run(&mut buffer, "o cr").is_err());
+        assert!(run(&mut buffer, "1o").is_err());
+    }
+
+    #[test]
+    fn test_read_strips_crlf() {
+        let in_filename = "/tmp/ed_read_crlf.txt";
+        std::fs::write(in_filename, "x\r\ny\r\n").unwrap();
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, &format!("1r {}", in_filename)).expect("read failed");
+        assert_eq!(buffer.line_ending, LineEnding::Lf);
+        assert_eq!(buffer.lines.unwrap()[1..3], text_lines(&["x", "y"]));
+    }
+
+    #[rstest]
+    #[case(false, "one\ntwo\n")]
+    #[case(true, "one\ntwo")]
+    fn rstest_missing_newline(#[case] keep_missing_newline: bool, #[case] expected: &str) {
+        let filename = format!("/tmp/ed_missing_newline_{}.txt", keep_missing_newline);
+        std::fs::write(&filename, "one\ntwo").unwrap();
+        let mut buffer = LineBuffer {
+            keep_missing_newline,
+            ..LineBuffer::empty()
+        };
+        run(&mut buffer, &format!("e {}", filename)).expect("edit failed");
+        assert_eq!(buffer.missing_newline, keep_missing_newline);
+        run(&mut buffer, "w").expect("write failed");
+        assert_eq!(std::fs::read_to_string(&filename).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_edit_non_utf8_file() {
+        let filename = "/tmp/ed_non_utf8.txt";
+        std::fs::write(filename, b"caf\xe9\nna\xefve\n").unwrap();
+        let mut buffer = LineBuffer::empty();
+        run(&mut buffer, &format!("e {}", filename)).expect("edit failed");
+        run(&mut buffer, "1s/(?-u:\\xe9)/e/").expect("substitute failed");
+        run(&mut buffer, "2t0").expect("transfer failed");
+        run(&mut buffer, "w").expect("write failed");
+        assert_eq!(std::fs::read(filename).unwrap(), b"na\xefve\ncafe\nna\xefve\n");
+    }
+
+    #[test]
+    fn test_script_non_utf8_round_trip() {
+        use crate::ed_command_parser::parse_line;
+        use crate::input_mode::{read_command, ScriptSource};
+
+        let filename = "/tmp/ed_script_non_utf8.txt";
+        std::fs::write(filename, "one\n").unwrap();
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        let mut source = ScriptSource::new(&b"1s/one/caf\xe9/\na\n\xff\0x\n.\n2s/\xff/y/\nw\n"[..]);
+        while let Ok(line) = read_command(&mut source, "") {
+            let command = parse_line(&line).expect("bad line parse");
+            command_runner(&mut buffer, &command, &mut source).expect("command failed");
+        }
+        assert_eq!(std::fs::read(filename).unwrap(), b"caf\xe9\ny\0x\n");
+    }
+
+    #[rstest]
+    #[case("1pl", 5, 1)]
+    #[case("1,2nl", 5, 2)]
+    #[case("2ln", 5, 2)]
+    #[case("2dl", 4, 1)]
+    #[case("2,3dn", 3, 1)]
+    #[case("1a\nnew\n.", 6, 2)]
+    fn rstest_print_suffix_accepted(#[case] line: &str, #[case] len: usize, #[case] current: usize) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let mut lines = line.split('\n');
+        let command = crate::ed_command_parser::parse_line(lines.next().unwrap().as_bytes()).unwrap();
+        let mut input: VecDeque<Vec<u8>> = lines.map(|s| s.as_bytes().to_vec()).collect();
+        command_runner(&mut buffer, &command, &mut input).expect("command failed");
+        assert_eq!(buffer.len(), len);
+        assert_eq!(buffer.current_line, current);
+    }
+
+    #[rstest]
+    #[case("1dx")]
+    #[case("1px")]
+    #[case("1nx")]
+    #[case("1lx")]
+    #[case("1ax")]
+    #[case("1ix")]
+    #[case("1cx")]
+    #[case("ux")]
+    fn rstest_invalid_print_suffix(#[case] line: &str) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "1d").expect("delete failed");
+        let mut input: VecDeque<Vec<u8>> = VecDeque::from([b"new".to_vec(), b".".to_vec()]);
+        let command = crate::ed_command_parser::parse_line(line.as_bytes()).unwrap();
+        let error = command_runner(&mut buffer, &command, &mut input).expect_err("suffix should be refused");
+        assert!(matches!(error.downcast_ref(), Some(EdCommandError::InvalidCommandSuffix)));
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(input.len(), 2, "Input mode should not start");
+    }
+
+    #[test]
+    fn test_edit_without_filename() {
+        let mut buffer = LineBuffer::empty();
+        assert!(run(&mut buffer, "e").is_err());
+    }
+
     #[test]
     fn test_print_with_numbers_empty_buffer() {
         let buffer = LineBuffer::empty();
@@ -415,11 +1635,7 @@ mod tests {
     #[test]
     fn test_print_with_numbers_invalid_range() {
         let buffer = LineBuffer {
-            lines: Some(vec![
-                "line one".to_string(),
-                "line two".to_string(),
-                "line three".to_string(),
-            ]),
+            lines: Some(text_lines(&["line one", "line two", "line three"])),
             ..LineBuffer::empty()
         };
 
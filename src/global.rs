@@ -0,0 +1,342 @@
+//! This module handles the global commands `g`, `v`, `G` and `V`.
+//!
+//! * parse_global: Split `g` arguments into a pattern and a command list.
+//! * global: Mark matching lines, then run the command list on each one.
+//! * interactive_global: Mark matching lines, then prompt for a command for each one.
+
+use crate::buffer::line_array_buffer::LineBuffer;
+use crate::command_structs::{EdCommand, PrintSuffix};
+use crate::ed_command_error::EdCommandError;
+use crate::ed_command_parser::parse_line;
+use crate::ed_commands::*;
+use crate::input_mode::{read_command, LineSource};
+use crate::search::{pattern_regex, read_pattern, remember_pattern, split_delimiter};
+
+use std::collections::VecDeque;
+use std::error::Error;
+
+/// Parses the arguments of a `g` or `v` command.
+///
+/// The command list follows the closing delimiter. Lines of the list are
+/// separated by escaped newlines, and an empty list means `p`.
+///
+/// # Arguments
+///
+/// * `args` - The bytes after the `g` or `v` command, if any.
+///
+/// # Returns
+///
+/// * `Result<(Vec<u8>, Vec<Vec<u8>>), EdCommandError>` - The pattern and the lines of the command list.
+pub fn parse_global(args: Option<&[u8]>) -> Result<(Vec<u8>, Vec<Vec<u8>>), EdCommandError> {
+    let (pattern, mut rest) = split_pattern(args.unwrap_or_default())?;
+    if rest.is_empty() {
+        return Ok((pattern, vec![b"p".to_vec()]));
+    }
+    let mut command_list = vec![];
+    while let Some(end) = rest.windows(2).position(|pair| pair == b"\\\n") {
+        command_list.push(rest[..end].to_vec());
+        rest = &rest[end + 2..];
+    }
+    command_list.push(rest.to_vec());
+    Ok((pattern, command_list))
+}
+
+/// Splits `/re/rest` into the pattern and the bytes after its closing delimiter.
+fn split_pattern(args: &[u8]) -> Result<(Vec<u8>, &[u8]), EdCommandError> {
+    let (delimiter, mut rest) = match split_delimiter(args) {
+        Some((c, rest)) if !c.is_ascii_alphanumeric() && !c.is_whitespace() && c != '\\' => (c, rest),
+        _ => return Err(EdCommandError::InvalidPatternDelimiter),
+    };
+
+    let (pattern, terminated) = read_pattern(&mut rest, delimiter);
+    if !terminated {
+        return Err(EdCommandError::MissingPatternDelimiter);
+    }
+    Ok((pattern, rest))
+}
+
+/// Runs a command list once for every line in the range that matches (`g`)
+/// or does not match (`v`) a regular expression.
+///
+/// All matching lines are marked before any command runs. Each marked line
+/// in turn becomes the current line and the command list is run on it.
+/// Marks move with their lines when the command list inserts or deletes
+/// lines, and lines deleted before their turn are skipped. Text for `a`,
+/// `i` and `c` is read from the rest of the command list.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and the `g` arguments.
+/// * `invert` - `true` for `v`, running the list on lines that do not match.
+///
+/// # Returns
+///
+/// Returns the `REPLStatus` of the last command run, or the first error raised by the command list.
+pub fn global(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    invert: bool,
+) -> Result<REPLStatus, Box<dyn Error>> {
+    let (pattern, command_list) = parse_global(command.command_args.as_deref())?;
+    mark_lines(buffer, command, &pattern, invert)?;
+
+    buffer.global_active = true;
+    let result = run_marked_lines(buffer, &command_list);
+    buffer.global_active = false;
+    buffer.global_marks.clear();
+    result
+}
+
+/// Prints each line in the range that matches (`G`) or does not match (`V`)
+/// a regular expression, then reads a one-line command from `input` and runs
+/// it with that line as the current line.
+///
+/// An empty command skips the line, and `&` repeats the previous command.
+/// Marks move with their lines as with `g`.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and the `G` arguments.
+/// * `invert` - `true` for `V`, prompting on lines that do not match.
+/// * `input` - The `LineSource` commands and input mode text are read from.
+///
+/// # Returns
+///
+/// Returns the `REPLStatus` of the last command run, or the first error raised by a command.
+pub fn interactive_global(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    invert: bool,
+    input: &mut dyn LineSource,
+) -> Result<REPLStatus, Box<dyn Error>> {
+    let (pattern, rest) = split_pattern(command.command_args.as_deref().unwrap_or_default())?;
+    if !rest.is_empty() {
+        return Err(Box::new(EdCommandError::InvalidCommandSuffix));
+    }
+    mark_lines(buffer, command, &pattern, invert)?;
+
+    buffer.global_active = true;
+    let result = prompt_marked_lines(buffer, input);
+    buffer.global_active = false;
+    buffer.global_marks.clear();
+    result
+}
+
+/// Fills `buffer.global_marks` with the lines in the command's range that
+/// match `pattern`, or that do not match it when `invert` is set.
+fn mark_lines(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    pattern: &[u8],
+    invert: bool,
+) -> Result<(), EdCommandError> {
+    if buffer.global_active {
+        return Err(EdCommandError::NestedGlobal);
+    }
+    if buffer.len() == 0 {
+        return Err(EdCommandError::EmptyBuffer);
+    }
+
+    let re = pattern_regex(pattern, buffer)?;
+    remember_pattern(pattern, buffer);
+
+    let low = address_to_index(command.address1.clone(), buffer)?;
+    let high = address_to_index(command.address2.clone(), buffer)?;
+    let lines = buffer.lines.as_deref().unwrap_or_default();
+    buffer.global_marks = (low..=high)
+        .filter(|&index| re.is_match(&lines[index]) != invert)
+        .map(|index| index + 1)
+        .collect();
+    Ok(())
+}
+
+/// Prints each line in `buffer.global_marks` and runs the command read for it.
+fn prompt_marked_lines(
+    buffer: &mut LineBuffer,
+    input: &mut dyn LineSource,
+) -> Result<REPLStatus, Box<dyn Error>> {
+    let mut status = REPLStatus::Continue;
+    let mut previous: Option<Vec<u8>> = None;
+    while let Some(line) = buffer.global_marks.pop_front() {
+        buffer.current_line = line;
+        print_suffix(buffer, Some(PrintSuffix::Print));
+
+        let text = match read_command(input, "") {
+            Ok(text) => text,
+            Err(_) => break,
+        };
+        let text = match text.as_slice() {
+            b"" => continue,
+            b"&" => previous.clone().ok_or(EdCommandError::NoPreviousCommand)?,
+            _ => text,
+        };
+
+        let line_command = parse_line(&text)?;
+        status = command_runner(buffer, &line_command, input)?;
+        previous = Some(text);
+        if status == REPLStatus::Quit {
+            break;
+        }
+    }
+    Ok(status)
+}
+
+/// Runs `command_list` with each line in `buffer.global_marks` as the current line.
+fn run_marked_lines(
+    buffer: &mut LineBuffer,
+    command_list: &[Vec<u8>],
+) -> Result<REPLStatus, Box<dyn Error>> {
+    let mut status = REPLStatus::Continue;
+    while let Some(line) = buffer.global_marks.pop_front() {
+        buffer.current_line = line;
+        let mut list: VecDeque<Vec<u8>> = command_list.iter().cloned().collect();
+        while let Some(text) = list.pop_front() {
+            let list_command = parse_line(&text)?;
+            status = command_runner(buffer, &list_command, &mut list)?;
+            if status == REPLStatus::Quit {
+                return Ok(status);
+            }
+        }
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::line_array_buffer::run;
+    use crate::command_structs::Address;
+    use rstest::rstest;
+
+    fn lines(buffer: &LineBuffer) -> Vec<&str> {
+        buffer
+            .lines
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("/re/", "re", vec!["p"])]
+    #[case("/re/d", "re", vec!["d"])]
+    #[case("|a/b|s/x/y/\\\np", "a/b", vec!["s/x/y/", "p"])]
+    #[case("/a\\/b/n", "a/b", vec!["n"])]
+    fn rstest_parse_global(
+        #[case] args: &str,
+        #[case] pattern: &str,
+        #[case] list: Vec<&str>,
+    ) {
+        let (actual_pattern, actual_list) = parse_global(Some(args.as_bytes())).unwrap();
+        assert_eq!(actual_pattern, pattern.as_bytes());
+        assert_eq!(actual_list, list.iter().map(|s| s.as_bytes()).collect::<Vec<_>>());
+    }
+
+    #[rstest]
+    #[case(None)]
+    #[case(Some("/re"))]
+    #[case(Some("are/p"))]
+    fn rstest_parse_global_errors(#[case] args: Option<&str>) {
+        assert!(parse_global(args.map(str::as_bytes)).is_err());
+    }
+
+    #[test]
+    fn test_global_delete() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "g/o/d").unwrap();
+        assert_eq!(lines(&buffer), vec!["three", "five"]);
+    }
+
+    #[test]
+    fn test_inverse_global_substitute() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "v/o/s/$/!/").unwrap();
+        assert_eq!(lines(&buffer), vec!["one", "two", "three!", "four", "five!"]);
+        assert_eq!(buffer.current_line, 5);
+    }
+
+    #[test]
+    fn test_global_marks_follow_inserted_lines() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "g/^t/a\\\n--\\\n.").unwrap();
+        assert_eq!(
+            lines(&buffer),
+            vec!["one", "two", "--", "three", "--", "four", "five"]
+        );
+    }
+
+    #[test]
+    fn test_global_marks_follow_moved_lines() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "g/o/.,+1m0").unwrap();
+        assert_eq!(lines(&buffer), vec!["four", "five", "two", "three", "one"]);
+        assert!(buffer.global_marks.is_empty());
+    }
+
+    #[test]
+    fn test_global_skips_deleted_lines() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        run(&mut buffer, "g/./.,.+1d").unwrap_err();
+        assert_eq!(lines(&buffer), vec!["five"]);
+        assert!(buffer.global_marks.is_empty());
+        assert!(!buffer.global_active);
+    }
+
+    #[test]
+    fn test_global_range_and_multiline_list() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let command = EdCommand {
+            address1: Address::Absolute(2),
+            address2: Address::Absolute(4),
+            command: Some("g".to_string()),
+            command_args: Some(b"/o/s/o/0/\\\ns/$/./".to_vec()),
+            ..EdCommand::default()
+        };
+        command_runner(&mut buffer, &command, &mut VecDeque::new()).unwrap();
+        assert_eq!(lines(&buffer), vec!["one", "tw0.", "three", "f0ur.", "five"]);
+    }
+
+    #[test]
+    fn test_interactive_global() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let command = parse_line(b"G/o/").unwrap();
+        let mut input: VecDeque<Vec<u8>> = ["s/o/0/", "", "&", "p"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        command_runner(&mut buffer, &command, &mut input).unwrap();
+        assert_eq!(lines(&buffer), vec!["0ne", "two", "three", "f0ur", "five"]);
+        assert_eq!(input, VecDeque::from([b"p".to_vec()]));
+    }
+
+    #[test]
+    fn test_interactive_inverse_global_reads_input_mode_text() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let command = parse_line(b"V/o/").unwrap();
+        let mut input: VecDeque<Vec<u8>> = ["a", "new", ".", "d"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        command_runner(&mut buffer, &command, &mut input).unwrap();
+        assert_eq!(lines(&buffer), vec!["one", "two", "three", "new", "four"]);
+    }
+
+    #[rstest]
+    #[case("G/o/p")]
+    #[case("G/o/d")]
+    fn rstest_interactive_global_rejects_command_list(#[case] line: &str) {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        let command = parse_line(line.as_bytes()).unwrap();
+        assert!(command_runner(&mut buffer, &command, &mut VecDeque::new()).is_err());
+    }
+
+    #[test]
+    fn test_nested_global_is_an_error() {
+        let mut buffer = LineBuffer::from_file("test_files/one.txt").unwrap();
+        assert!(run(&mut buffer, "g/o/g/e/d").is_err());
+        assert_eq!(buffer.len(), 5);
+    }
+}
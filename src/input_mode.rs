@@ -1,32 +1,102 @@
-use rustyline::error::ReadlineError;
 use crate::ed_command_error::EdCommandError;
+use rustyline::error::ReadlineError;
+use std::collections::VecDeque;
+use std::io::BufRead;
+
+/// A source of lines for the command prompt and input mode.
+///
+/// The interactive editor reads from `rustyline`, script mode reads from a
+/// `ScriptSource`, and command lists run by `g` and `v` read from a queue of
+/// lines. Lines are bytes, so text that is not UTF-8 reaches the buffer
+/// unchanged.
+pub trait LineSource {
+    /// Reads one line without its trailing newline. Returns
+    /// `ReadlineError::Eof` when no lines remain.
+    fn read_line(&mut self, prompt: &str) -> Result<Vec<u8>, ReadlineError>;
+}
+
+impl LineSource for rustyline::DefaultEditor {
+    fn read_line(&mut self, prompt: &str) -> Result<Vec<u8>, ReadlineError> {
+        let line = self.readline(prompt)?;
+        self.add_history_entry(line.as_str())?;
+        Ok(line.into_bytes())
+    }
+}
+
+impl LineSource for VecDeque<Vec<u8>> {
+    fn read_line(&mut self, _prompt: &str) -> Result<Vec<u8>, ReadlineError> {
+        self.pop_front().ok_or(ReadlineError::Eof)
+    }
+}
+
+/// Reads lines from any `BufRead`, such as standard input in script mode,
+/// without line editing, history or a prompt. Lines are read as bytes and
+/// need not be UTF-8.
+pub struct ScriptSource<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> ScriptSource<R> {
+    pub fn new(reader: R) -> Self {
+        ScriptSource { reader }
+    }
+}
+
+impl<R: BufRead> LineSource for ScriptSource<R> {
+    fn read_line(&mut self, _prompt: &str) -> Result<Vec<u8>, ReadlineError> {
+        let mut line = vec![];
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Err(ReadlineError::Eof);
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        Ok(line)
+    }
+}
+
+/// Returns `true` if `line` ends with an odd number of backslashes, meaning
+/// the newline after it is escaped.
+pub fn ends_with_escape(line: &[u8]) -> bool {
+    line.iter().rev().take_while(|&&byte| byte == b'\\').count() % 2 == 1
+}
 
-pub fn input_mode() -> Result<Vec<String>, EdCommandError> {
-    let mut rl = rustyline::DefaultEditor::new()?;
+/// Reads a command line, joining lines that end in an escaped newline.
+/// The backslash is kept so that `s` and `g` can see the escaped newline.
+pub fn read_command(source: &mut dyn LineSource, prompt: &str) -> Result<Vec<u8>, ReadlineError> {
+    let mut line = source.read_line(prompt)?;
+    while ends_with_escape(&line) {
+        match source.read_line("") {
+            Ok(next) => {
+                line.push(b'\n');
+                line.extend_from_slice(&next);
+            }
+            Err(_) => break,
+        }
+    }
+    Ok(line)
+}
 
-    let mut input_buffer: Vec<String> = vec![];
+pub fn input_mode(source: &mut dyn LineSource) -> Result<Vec<Vec<u8>>, EdCommandError> {
+    let mut input_buffer: Vec<Vec<u8>> = vec![];
 
     loop {
-        let readline = rl.readline("");
+        let readline = source.read_line("");
 
         match readline {
-            Ok(line) => {
-                rl.add_history_entry(line.as_str())?;
-                match line.trim() {
-                    "." => {
-                        return Ok(input_buffer);
-                    }
-                    _ => {
-                        input_buffer.push(line);
-                    }
+            Ok(line) => match line.trim_ascii() {
+                b"." => {
+                    return Ok(input_buffer);
                 }
-            }
+                _ => {
+                    input_buffer.push(line);
+                }
+            },
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
                 break;
             }
             Err(ReadlineError::Eof) => {
-                println!("CTRL-D");
                 break;
             }
             Err(err) => {
@@ -38,3 +108,56 @@ pub fn input_mode() -> Result<Vec<String>, EdCommandError> {
 
     Ok(input_buffer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn queue(lines: &[&str]) -> VecDeque<Vec<u8>> {
+        lines.iter().map(|s| s.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_input_mode_stops_at_dot() {
+        let mut source = queue(&["one", "two", ".", "p"]);
+        let lines = input_mode(&mut source).unwrap();
+        assert_eq!(lines, vec![b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(source, queue(&["p"]));
+    }
+
+    #[test]
+    fn test_input_mode_stops_at_eof() {
+        let mut source = queue(&["one"]);
+        let lines = input_mode(&mut source).unwrap();
+        assert_eq!(lines, vec![b"one".to_vec()]);
+    }
+
+    #[test]
+    fn test_script_source_reads_lines() {
+        let mut source = ScriptSource::new("a\none\n.\nlast".as_bytes());
+        assert_eq!(read_command(&mut source, "").unwrap(), b"a");
+        assert_eq!(input_mode(&mut source).unwrap(), vec![b"one".to_vec()]);
+        assert_eq!(source.read_line("").unwrap(), b"last");
+        assert!(matches!(source.read_line(""), Err(ReadlineError::Eof)));
+    }
+
+    #[rstest]
+    #[case(&b"caf\xe9"[..])]
+    #[case(&b"\xff\0x"[..])]
+    #[case("x\u{10ff80}y".as_bytes())]
+    fn rstest_script_source_keeps_bytes(#[case] line: &[u8]) {
+        let script = [line, b"\n.\n"].concat();
+        let mut source = ScriptSource::new(script.as_slice());
+        assert_eq!(input_mode(&mut source).unwrap(), vec![line.to_vec()]);
+    }
+
+    #[rstest]
+    #[case(&["p"], "p")]
+    #[case(&["s/a/b\\", "c/", "p"], "s/a/b\\\nc/")]
+    #[case(&["s/a/b\\\\", "p"], "s/a/b\\\\")]
+    fn rstest_read_command(#[case] lines: &[&str], #[case] expected: &str) {
+        let mut source = queue(lines);
+        assert_eq!(read_command(&mut source, "").unwrap(), expected.as_bytes());
+    }
+}
@@ -0,0 +1,73 @@
+//! This module formats lines for the `l` command, which shows characters
+//! that `p` would hide.
+//!
+//! * list_line: Escape a line and fold it to the output width.
+
+/// The width that listed lines are folded to, including the trailing `\` or `$`.
+pub const LIST_WIDTH: usize = 72;
+
+/// Returns the escaped form of one byte: `\a \b \f \n \r \t \v` for control
+/// characters with a C escape, `\\` for backslash, `\ddd` octal for other
+/// non-printable bytes, and the character itself otherwise.
+fn escape_byte(byte: u8) -> String {
+    match byte {
+        0x07 => "\\a".to_string(),
+        0x08 => "\\b".to_string(),
+        0x0c => "\\f".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\r' => "\\r".to_string(),
+        b'\t' => "\\t".to_string(),
+        0x0b => "\\v".to_string(),
+        b'\\' => "\\\\".to_string(),
+        b' '..=b'~' => (byte as char).to_string(),
+        _ => format!("\\{:03o}", byte),
+    }
+}
+
+/// Formats a line unambiguously for the `l` command.
+///
+/// The line ends with `$`, and lines longer than `width` are folded into
+/// several output lines that end with `\`. Escape sequences are never split.
+///
+/// # Arguments
+///
+/// * `line` - The bytes of the line, without its newline.
+/// * `width` - The most characters in each output line, including the trailing `\` or `$`.
+///
+/// # Returns
+///
+/// * `Vec<String>` - The output lines.
+pub fn list_line(line: &[u8], width: usize) -> Vec<String> {
+    let mut rows = vec![];
+    let mut row = String::new();
+    for escaped in line.iter().copied().map(escape_byte) {
+        if row.len() + escaped.len() > width.saturating_sub(1) && !row.is_empty() {
+            row.push('\\');
+            rows.push(std::mem::take(&mut row));
+        }
+        row.push_str(&escaped);
+    }
+    row.push('$');
+    rows.push(row);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("plain text", LIST_WIDTH, vec!["plain text$"])]
+    #[case("", LIST_WIDTH, vec!["$"])]
+    #[case("a\tb\r", LIST_WIDTH, vec!["a\\tb\\r$"])]
+    #[case("\x07\x08\x0c\x0b\n", LIST_WIDTH, vec!["\\a\\b\\f\\v\\n$"])]
+    #[case("back\\slash", LIST_WIDTH, vec!["back\\\\slash$"])]
+    #[case("nul\0del\x7f", LIST_WIDTH, vec!["nul\\000del\\177$"])]
+    #[case("é", LIST_WIDTH, vec!["\\303\\251$"])]
+    #[case("abcdefgh", 5, vec!["abcd\\", "efgh$"])]
+    #[case("abc\tdefg", 5, vec!["abc\\", "\\tde\\", "fg$"])]
+    fn rstest_list_line(#[case] line: &str, #[case] width: usize, #[case] expected: Vec<&str>) {
+        assert_eq!(list_line(line.as_bytes(), width), expected);
+    }
+}
@@ -2,71 +2,129 @@ mod buffer;
 mod command_parser;
 mod ed_command_parser;
 mod ed_commands;
+mod global;
 mod input_mode;
+mod list;
 mod modify;
+mod search;
+mod shell;
+mod substitute;
 mod ed_command_error;
 mod command_structs;
 
 use crate::buffer::line_array_buffer::LineBuffer;
 use crate::command_parser::parse_args::parse_args;
+use crate::command_structs::EdCommand;
 use std::env;
 use std::error::Error;
+use std::process::ExitCode;
 
-use ed_commands::REPLStatus;
+use ed_commands::{
+    append_missing_newline, check_filename, print_byte_count, report_error, REPLStatus,
+};
+use input_mode::{read_command, LineSource, ScriptSource};
 use rustyline::error::ReadlineError;
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> Result<ExitCode, Box<dyn Error>> {
     let config = parse_args(env::args_os().collect())?;
-    let mut rl = rustyline::DefaultEditor::new()?;
     let prompt = config.prompt.as_deref().unwrap_or("");
 
     if config.help {
-        return Ok(());
+        return Ok(ExitCode::SUCCESS);
     }
 
     let mut buffer = if let Some(filename) = config.filename {
+        if config.restricted {
+            check_filename(&filename)?;
+        }
         LineBuffer::from_file(&filename)?
     } else {
         LineBuffer::empty()
     };
+    buffer.explain_errors = config.verbose;
+    buffer.script_mode = config.script;
+    buffer.restricted = config.restricted;
+    buffer.keep_missing_newline = config.keep_missing_newline;
+    if let Some(count) = buffer.char_length() {
+        print_byte_count(&buffer, count);
+    }
+    append_missing_newline(&mut buffer);
+    // like `e`, editing starts on the last line of the file
+    buffer.current_line = buffer.len();
+    buffer.strip_trailing_cr = config.strip_trailing_cr;
+    if buffer.strip_trailing_cr {
+        buffer.strip_carriage_returns();
+    }
 
-    loop {
-        let readline = rl.readline(prompt);
+    // script mode reads commands without line editing
+    let mut script;
+    let mut editor;
+    let rl: &mut dyn LineSource = if config.script {
+        script = ScriptSource::new(std::io::stdin().lock());
+        &mut script
+    } else {
+        editor = rustyline::DefaultEditor::new()?;
+        // leave room for the prompt when scrolling with `z`
+        if let Some((_, rows)) = editor.dimensions() {
+            buffer.window_lines = rows.saturating_sub(2).max(1);
+        }
+        &mut editor
+    };
 
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(line.as_str())?;
-                let command = ed_command_parser::parse_line(line.as_str())?;
-                if config.diagnostics {
-                    println!("{:#?}", command);
+    let mut failed = false;
+    loop {
+        let result = match read_command(rl, prompt) {
+            Ok(line) => match ed_command_parser::parse_line(&line) {
+                Ok(command) => {
+                    if config.diagnostics {
+                        println!("{:#?}", command);
+                    }
+                    ed_commands::command_runner(&mut buffer, &command, rl)
                 }
-                let result_or_err = ed_commands::command_runner(&mut buffer, &command);
-                let result = match result_or_err {
-                    Ok(result) => result,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        continue;
+                Err(e) => {
+                    if config.diagnostics {
+                        println!("{:#?}", e);
                     }
-                };
-
-                if result == REPLStatus::Quit {
-                    break;
+                    Err(e.into())
                 }
-            }
+            },
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
                 break;
             }
+            // a script quits silently at end of input
+            Err(ReadlineError::Eof) if config.script => Ok(REPLStatus::Quit),
+            // end of input quits like `q`, warning once about unsaved changes
             Err(ReadlineError::Eof) => {
-                println!("CTRL-D");
-                break;
+                let command = EdCommand {
+                    command: Some("q".to_string()),
+                    ..EdCommand::default()
+                };
+                ed_commands::command_runner(&mut buffer, &command, rl)
             }
             Err(err) => {
                 println!("Error: {:?}", err);
+                failed = true;
                 break;
             }
+        };
+
+        match result {
+            Ok(REPLStatus::Quit) => break,
+            Ok(REPLStatus::Continue) => {}
+            Err(e) => {
+                report_error(&mut buffer, e.as_ref());
+                failed = true;
+                // a script stops at its first error
+                if config.script {
+                    break;
+                }
+            }
         }
     }
 
-    Ok(())
+    if failed && !config.loose_exit_status {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
 }
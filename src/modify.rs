@@ -3,12 +3,19 @@
 //! * insert: Insert lines before address.
 //! * append: Append lines after address.
 //! * correct: Overwrite range with new text.
+//! * delete: Remove a range of lines.
+//! * join: Join a range of lines into one line.
+//! * move_lines: Move a range of lines after another line.
+//! * transfer: Copy a range of lines after another line.
+//! * yank: Copy a range of lines into the cut buffer.
+//! * put: Append the cut buffer after a line.
+//! * undo: Revert the last command that changed the buffer.
 
 use crate::buffer::line_array_buffer::LineBuffer;
 use crate::ed_command_error::EdCommandError;
 use crate::command_structs::{Address, EdCommand};
 use crate::ed_commands::*;
-use crate::input_mode::input_mode;
+use crate::input_mode::{input_mode, LineSource};
 
 /// Inserts a vector of lines into the `LineBuffer` before the specified location.
 ///
@@ -22,27 +29,22 @@ use crate::input_mode::input_mode;
 ///
 /// * `buffer` - A mutable `LineBuffer` reference.
 /// * `location` - The `Address` specifying where to insert the lines in the buffer.
-/// * `lines` - A vector of strings (`Vec<String>`) containing the lines to be inserted.
+/// * `lines` - A vector of lines of bytes (`Vec<Vec<u8>>`) containing the lines to be inserted.
 ///
 /// # Returns
 ///
-/// Returns the index of the last line that was inserted.
+/// Returns the index of the last line that was inserted, or an error if the address cannot be resolved.
 pub fn insert_into_buffer(
     buffer: &mut LineBuffer,
     location: &Address,
-    lines: Vec<String>,
-) -> usize {
-    let index = address_to_index(location.clone(), buffer);
+    lines: Vec<Vec<u8>>,
+) -> Result<usize, EdCommandError> {
+    let index = address_to_index(location.clone(), buffer)?;
     let input_lines_len = lines.len();
-    match &mut buffer.lines {
-        None => buffer.lines = Some(lines),
-        Some(buffer_lines) => {
-            buffer_lines.splice(index..index, lines);
-        }
-    };
+    buffer.splice_lines(index, index, lines);
     // set current line to end of inserted text.
     buffer.current_line = index + input_lines_len;
-    buffer.current_line
+    Ok(buffer.current_line)
 }
 
 /// Inserts lines into the buffer at the specified address.
@@ -50,20 +52,28 @@ pub fn insert_into_buffer(
 /// This function takes an `EdCommand` with an address and inserts the input lines
 /// into the `LineBuffer` before the location specified by the command.
 /// If a range is specified, inserts at address2. Updates current line
-/// to end of inserted text.
+/// to end of inserted text. Accepts a `p`, `n` or `l` print suffix.
 ///
 /// # Arguments
 ///
 /// * `buffer` - A mutable reference to the `LineBuffer` where the lines will be inserted.
-/// * `command` - A reference to the `EdCommand` containing the address and other command details.
+/// * `command` - A reference to the `EdCommand` containing the address and optional print suffix.
+/// * `input` - The `LineSource` to read the new lines from.
 ///
 /// # Returns
 ///
-/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if user input fails.
-pub fn insert(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
-    let input_lines = input_mode()?;
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the suffix is invalid
+/// or user input fails.
+pub fn insert(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    input: &mut dyn LineSource,
+) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    let input_lines = input_mode(input)?;
 
-    let _index = insert_into_buffer(buffer, &command.address2, input_lines);
+    insert_into_buffer(buffer, &command.address2, input_lines)?;
+    print_suffix(buffer, suffix);
 
     Ok(REPLStatus::Continue)
 }
@@ -80,31 +90,26 @@ pub fn insert(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus
 ///
 /// * `buffer` - A mutable `LineBuffer` reference.
 /// * `location` - The `Address` specifying where to insert the lines in the buffer.
-/// * `lines` - A vector of strings (`Vec<String>`) containing the lines to be inserted.
+/// * `lines` - A vector of lines of bytes (`Vec<Vec<u8>>`) containing the lines to be inserted.
 ///
 /// # Returns
 ///
-/// Returns the index of the last line that was appended.
+/// Returns the index of the last line that was appended, or an error if the address cannot be resolved.
 pub fn append_into_buffer(
     buffer: &mut LineBuffer,
     location: &Address,
-    lines: Vec<String>,
-) -> usize {
-    let mut index = address_to_index(location.clone(), buffer) + 1;
+    lines: Vec<Vec<u8>>,
+) -> Result<usize, EdCommandError> {
+    let mut index = address_to_index(location.clone(), buffer)? + 1;
     // special case: appending to address 0 inserts *before* line 1
     if *location == Address::Absolute(0) {
         index -= index;
     }
     let input_lines_len = lines.len();
-    match &mut buffer.lines {
-        None => buffer.lines = Some(lines),
-        Some(buffer_lines) => {
-            buffer_lines.splice(index..index, lines);
-        }
-    };
+    buffer.splice_lines(index, index, lines);
     // set current line to end of inserted text.
     buffer.current_line = index + input_lines_len;
-    buffer.current_line
+    Ok(buffer.current_line)
 }
 
 /// Appends lines into the buffer after the specified address.
@@ -112,31 +117,41 @@ pub fn append_into_buffer(
 /// This function takes an `EdCommand` with an address and inserts the input lines
 /// into the `LineBuffer` after the location specified by the command.
 /// If a range is specified, inserts at address2. Updates current line
-/// to end of inserted text.
+/// to end of inserted text. Accepts a `p`, `n` or `l` print suffix.
 ///
 /// # Arguments
 ///
 /// * `buffer` - A mutable reference to the `LineBuffer` where the lines will be appended.
-/// * `command` - A reference to the `EdCommand` containing the address.
+/// * `command` - A reference to the `EdCommand` containing the address and optional print suffix.
+/// * `input` - The `LineSource` to read the new lines from.
 ///
 /// # Returns
 ///
-/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if user input fails.
-pub fn append(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
-    let input_lines = input_mode()?;
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the suffix is invalid
+/// or user input fails.
+pub fn append(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    input: &mut dyn LineSource,
+) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    let input_lines = input_mode(input)?;
 
-    let _index = append_into_buffer(buffer, &command.address2, input_lines);
+    append_into_buffer(buffer, &command.address2, input_lines)?;
+    print_suffix(buffer, suffix);
 
     Ok(REPLStatus::Continue)
 }
 
-/// Insert lines into buffer replacing the specified range.
+/// Insert lines into buffer replacing the specified range. The replaced
+/// lines are saved in the cut buffer, and the current line becomes the last
+/// inserted line, whatever the size of the replaced range.
 ///
 /// # Arguments
 ///
 /// * `buffer` - A mutable `LineBuffer` reference.
 /// * `location` - The `Address` specifying where to insert the lines in the buffer.
-/// * `lines` - A vector of strings (`Vec<String>`) containing the lines to be inserted.
+/// * `lines` - A vector of lines of bytes (`Vec<Vec<u8>>`) containing the lines to be inserted.
 ///
 /// # Returns
 ///
@@ -145,73 +160,374 @@ pub fn correct_into_buffer(
     buffer: &mut LineBuffer,
     location1: &Address,
     location2: &Address,
-    lines: Vec<String>,
+    lines: Vec<Vec<u8>>,
 ) -> Result<usize, EdCommandError> {
-    let index1 = address_to_index(location1.clone(), buffer);
-    let index2 = address_to_index(location2.clone(), buffer);
+    let index1 = address_to_index(location1.clone(), buffer)?;
+    let index2 = address_to_index(location2.clone(), buffer)?;
     let input_lines_len = lines.len();
-    match &mut buffer.lines {
-        None => buffer.lines = Some(lines),
-        Some(buffer_lines) => {
-            buffer_lines.splice(index1..index2 + 1, lines);
-        }
-    };
+    buffer.cut_buffer = buffer.splice_lines(index1, index2 + 1, lines);
     // set current line to end of inserted text.
-    buffer.current_line = index2 + input_lines_len;
+    buffer.current_line = index1 + input_lines_len;
     Ok(buffer.current_line)
 }
 
-pub fn correct(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+/// Replaces the addressed lines with lines read in input mode.
+/// Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and optional print suffix.
+/// * `input` - The `LineSource` to read the new lines from.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the range or
+/// suffix is invalid or user input fails.
+pub fn correct(
+    buffer: &mut LineBuffer,
+    command: &EdCommand,
+    input: &mut dyn LineSource,
+) -> Result<REPLStatus, EdCommandError> {
     // handle special case where 0 is out of range
     // unlike insert and append
     if command.address1 == Address::Absolute(0) {
-        eprintln!("Invalid address");
-        return Err(EdCommandError::EmptyBuffer);
+        return Err(EdCommandError::InvalidRange);
     }
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
 
-    let input_lines = input_mode()?;
-    let new_location =
-        correct_into_buffer(buffer, &command.address1, &command.address2, input_lines)?;
-    println!("{}", new_location);
+    let input_lines = input_mode(input)?;
+    correct_into_buffer(buffer, &command.address1, &command.address2, input_lines)?;
+    print_suffix(buffer, suffix);
 
     Ok(REPLStatus::Continue)
 }
 
+/// Removes the lines in the specified range, saving them in the cut buffer.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable `LineBuffer` reference.
+/// * `location1` - The `Address` of the first line to delete.
+/// * `location2` - The `Address` of the last line to delete.
+///
+/// # Returns
+///
+/// Returns the line number before the deleted range, or `EdCommandError::EmptyBuffer` if the buffer is empty.
 pub fn delete_from_buffer(
     buffer: &mut LineBuffer,
     location1: &Address,
     location2: &Address,
 ) -> Result<usize, EdCommandError> {
-    let index1 = address_to_index(location1.clone(), buffer);
-    let index2 = address_to_index(location2.clone(), buffer);
-    match &mut buffer.lines {
-        None => return Err(EdCommandError::EmptyBuffer),
-        Some(buffer_lines) => {
-            buffer_lines.splice(index1..index2 + 1, vec![]);
-        }
-    };
+    let index1 = address_to_index(location1.clone(), buffer)?;
+    let index2 = address_to_index(location2.clone(), buffer)?;
+    if buffer.lines.is_none() {
+        return Err(EdCommandError::EmptyBuffer);
+    }
+    buffer.cut_buffer = buffer.splice_lines(index1, index2 + 1, vec![]);
     // set current line to beginning of deleted range.
     buffer.current_line = index1;
     Ok(buffer.current_line)
 }
 
+/// Deletes the addressed lines, saving them in the cut buffer.
+/// Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the range or suffix is invalid.
 pub fn delete(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
     // handle special case where 0 is out of range
     // unlike insert and append
     if command.address1 == Address::Absolute(0) {
         return Err(EdCommandError::InvalidRange);
     }
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
 
-    let new_location = delete_from_buffer(buffer, &command.address1, &command.address2)?;
-    println!("{}", new_location);
+    delete_from_buffer(buffer, &command.address1, &command.address2)?;
+    print_suffix(buffer, suffix);
 
     Ok(REPLStatus::Continue)
 }
-#[cfg(test)]
+/// Joins the lines in the specified range into a single line. The original
+/// lines are saved in the cut buffer.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable `LineBuffer` reference.
+/// * `location1` - The `Address` of the first line to join.
+/// * `location2` - The `Address` of the last line to join.
+///
+/// # Returns
+///
+/// Returns the line number of the joined line, or `EdCommandError::EmptyBuffer` if the buffer is empty.
+pub fn join_into_buffer(
+    buffer: &mut LineBuffer,
+    location1: &Address,
+    location2: &Address,
+) -> Result<usize, EdCommandError> {
+    let index1 = address_to_index(location1.clone(), buffer)?;
+    let index2 = address_to_index(location2.clone(), buffer)?;
+    let joined = match &buffer.lines {
+        None => return Err(EdCommandError::EmptyBuffer),
+        Some(buffer_lines) => buffer_lines[index1..=index2].concat(),
+    };
+    buffer.cut_buffer = buffer.splice_lines(index1, index2 + 1, vec![joined]);
+    // set current line to the joined line.
+    buffer.current_line = index1 + 1;
+    Ok(buffer.current_line)
+}
 
+/// Joins the addressed lines into one line. With no address the current and
+/// next lines are joined, and a single addressed line is left unchanged.
+/// Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the range or suffix is invalid.
+pub fn join(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if command.address1 == Address::Absolute(0) {
+        return Err(EdCommandError::InvalidRange);
+    }
+
+    if command.address1 != command.address2 {
+        join_into_buffer(buffer, &command.address1, &command.address2)?;
+    }
+    print_suffix(buffer, suffix);
+
+    Ok(REPLStatus::Continue)
+}
+
+/// Moves the lines in the specified range to after the destination line.
+/// Marks on the moved lines move with them.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable `LineBuffer` reference.
+/// * `location1` - The `Address` of the first line to move.
+/// * `location2` - The `Address` of the last line to move.
+/// * `destination` - The `Address` the lines are moved after. `Address::Absolute(0)` moves them to the top.
+///
+/// # Returns
+///
+/// Returns the line number of the last moved line, or `EdCommandError::InvalidDestination` if the
+/// destination is inside the moved range.
+pub fn move_into_buffer(
+    buffer: &mut LineBuffer,
+    location1: &Address,
+    location2: &Address,
+    destination: &Address,
+) -> Result<usize, EdCommandError> {
+    let index1 = address_to_index(location1.clone(), buffer)?;
+    let index2 = address_to_index(location2.clone(), buffer)?;
+    let dest = resolve_address(destination, buffer)?;
+    if dest > index1 && dest <= index2 {
+        return Err(EdCommandError::InvalidDestination);
+    }
+
+    let moved_marks: Vec<(char, usize)> = buffer
+        .marks
+        .iter()
+        .filter(|(_, &line)| line > index1 && line <= index2 + 1)
+        .map(|(&name, &line)| (name, line - index1 - 1))
+        .collect();
+    // lines still queued by a global command keep their place in the queue.
+    let queued = std::mem::take(&mut buffer.global_marks);
+
+    let count = index2 + 1 - index1;
+    let moved = buffer.splice_lines(index1, index2 + 1, vec![]);
+    let insert_at = if dest > index2 { dest - count } else { dest };
+    buffer.splice_lines(insert_at, insert_at, moved);
+
+    for (name, offset) in moved_marks {
+        buffer.marks.insert(name, insert_at + offset + 1);
+    }
+    buffer.global_marks = queued
+        .into_iter()
+        .map(|line| {
+            if line > index1 && line <= index2 + 1 {
+                return insert_at + line - index1;
+            }
+            let line = if line > index2 + 1 { line - count } else { line };
+            if line > insert_at {
+                line + count
+            } else {
+                line
+            }
+        })
+        .collect();
+    // set current line to the last moved line.
+    buffer.current_line = insert_at + count;
+    Ok(buffer.current_line)
+}
+
+/// Moves the addressed lines after the destination address.
+/// Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range, destination and optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the range,
+/// destination or suffix is invalid.
+pub fn move_lines(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if command.address1 == Address::Absolute(0) || buffer.len() == 0 {
+        return Err(EdCommandError::InvalidRange);
+    }
+
+    move_into_buffer(buffer, &command.address1, &command.address2, &command.destination)?;
+    print_suffix(buffer, suffix);
+
+    Ok(REPLStatus::Continue)
+}
+
+/// Copies the lines in the specified range to after the destination line.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable `LineBuffer` reference.
+/// * `location1` - The `Address` of the first line to copy.
+/// * `location2` - The `Address` of the last line to copy.
+/// * `destination` - The `Address` the copies are placed after. `Address::Absolute(0)` places them at the top.
+///
+/// # Returns
+///
+/// Returns the line number of the last copied line.
+pub fn transfer_into_buffer(
+    buffer: &mut LineBuffer,
+    location1: &Address,
+    location2: &Address,
+    destination: &Address,
+) -> Result<usize, EdCommandError> {
+    let index1 = address_to_index(location1.clone(), buffer)?;
+    let index2 = address_to_index(location2.clone(), buffer)?;
+    let copied = match &buffer.lines {
+        None => return Err(EdCommandError::EmptyBuffer),
+        Some(buffer_lines) => buffer_lines[index1..=index2].to_vec(),
+    };
+    let destination = Address::Absolute(resolve_address(destination, buffer)?);
+    append_into_buffer(buffer, &destination, copied)
+}
+
+/// Copies the addressed lines after the destination address.
+/// Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range, destination and optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the range or suffix is invalid.
+pub fn transfer(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if command.address1 == Address::Absolute(0) || buffer.len() == 0 {
+        return Err(EdCommandError::InvalidRange);
+    }
+
+    transfer_into_buffer(buffer, &command.address1, &command.address2, &command.destination)?;
+    print_suffix(buffer, suffix);
+
+    Ok(REPLStatus::Continue)
+}
+
+/// Copies the addressed lines into the cut buffer without changing the
+/// buffer. Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or an error if the range or suffix is invalid.
+pub fn yank(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if command.address1 == Address::Absolute(0) || buffer.len() == 0 {
+        return Err(EdCommandError::InvalidRange);
+    }
+
+    let index1 = address_to_index(command.address1.clone(), buffer)?;
+    let index2 = address_to_index(command.address2.clone(), buffer)?;
+    if let Some(lines) = &buffer.lines {
+        buffer.cut_buffer = lines[index1..=index2].to_vec();
+    }
+    print_suffix(buffer, suffix);
+
+    Ok(REPLStatus::Continue)
+}
+
+/// Appends the contents of the cut buffer after the addressed line, which
+/// may be `0`. Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the address and optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if the operation is successful, or `EdCommandError::NothingToPut`
+/// if the cut buffer is empty.
+pub fn put(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if buffer.cut_buffer.is_empty() {
+        return Err(EdCommandError::NothingToPut);
+    }
+
+    let lines = buffer.cut_buffer.clone();
+    append_into_buffer(buffer, &command.address2, lines)?;
+    print_suffix(buffer, suffix);
+
+    Ok(REPLStatus::Continue)
+}
+
+/// Undoes the last command that changed the buffer, including every change
+/// made by a global command list. Running `u` again redoes the command.
+/// Accepts a `p`, `n` or `l` print suffix.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the optional print suffix.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)`, `EdCommandError::InvalidCommandSuffix` for an invalid suffix, or
+/// `EdCommandError::NothingToUndo` if no command has changed the buffer.
+pub fn undo(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    let suffix = parse_print_suffix(command.command_args.as_deref())?;
+    if !buffer.undo() {
+        return Err(EdCommandError::NothingToUndo);
+    }
+    print_suffix(buffer, suffix);
+    Ok(REPLStatus::Continue)
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::buffer::line_array_buffer::{run, text_lines};
+    use crate::ed_command_parser::parse_line;
     use rstest::*;
+    use std::collections::VecDeque;
 
     #[fixture]
     #[once]
@@ -227,10 +543,10 @@ mod tests {
     fn test_basic_insert() {
         let mut buffer = LineBuffer::empty();
         let address = Address::Absolute(0);
-        let lines = vec!["one".to_string(), "two".to_string(), "three".to_string()];
-        let actual = insert_into_buffer(&mut buffer, &address, lines);
+        let lines = text_lines(&["one", "two", "three"]);
+        let actual = insert_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 3);
-        assert_eq!(buffer.lines.unwrap()[2], "three".to_string())
+        assert_eq!(buffer.lines.unwrap()[2], b"three")
     }
 
     #[rstest]
@@ -239,11 +555,11 @@ mod tests {
         // copy buffer to avoid clobbering original data
         let mut buffer = test_file1.clone();
         let address = Address::Absolute(2);
-        let lines = vec!["alpha".to_string()];
-        let actual = insert_into_buffer(&mut buffer, &address, lines);
+        let lines = vec![b"alpha".to_vec()];
+        let actual = insert_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 2);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 6);
-        assert_eq!(buffer.lines.as_ref().unwrap()[1], "alpha".to_string())
+        assert_eq!(buffer.lines.as_ref().unwrap()[1], b"alpha")
     }
 
     #[test]
@@ -251,11 +567,11 @@ mod tests {
     fn test_insert_into_empty() {
         let mut buffer = LineBuffer::empty();
         let address = Address::Absolute(1);
-        let lines = vec!["alpha".to_string()];
-        let actual = insert_into_buffer(&mut buffer, &address, lines);
+        let lines = vec![b"alpha".to_vec()];
+        let actual = insert_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 1);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 1);
-        assert_eq!(buffer.lines.as_ref().unwrap()[0], "alpha".to_string())
+        assert_eq!(buffer.lines.as_ref().unwrap()[0], b"alpha")
     }
 
     #[rstest]
@@ -265,10 +581,10 @@ mod tests {
         let mut buffer = test_file1.clone();
         let address = Address::Absolute(2);
         let lines = vec![];
-        let actual = insert_into_buffer(&mut buffer, &address, lines);
+        let actual = insert_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 1);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 5);
-        assert_eq!(buffer.lines.as_ref().unwrap()[1], "two".to_string())
+        assert_eq!(buffer.lines.as_ref().unwrap()[1], b"two")
     }
 
     #[rstest]
@@ -277,11 +593,11 @@ mod tests {
         // copy buffer to avoid clobbering original data
         let mut buffer = test_file1.clone();
         let address = Address::Absolute(2);
-        let lines = vec!["alpha".to_string()];
-        let actual = append_into_buffer(&mut buffer, &address, lines);
+        let lines = vec![b"alpha".to_vec()];
+        let actual = append_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 3);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 6);
-        assert_eq!(buffer.lines.as_ref().unwrap()[2], "alpha".to_string())
+        assert_eq!(buffer.lines.as_ref().unwrap()[2], b"alpha")
     }
 
     #[rstest]
@@ -290,11 +606,11 @@ mod tests {
         // copy buffer to avoid clobbering original data
         let mut buffer = test_file1.clone();
         let address = Address::Absolute(0);
-        let lines = vec!["alpha".to_string()];
-        let actual = append_into_buffer(&mut buffer, &address, lines);
+        let lines = vec![b"alpha".to_vec()];
+        let actual = append_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 1);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 6);
-        assert_eq!(buffer.lines.as_ref().unwrap()[0], "alpha".to_string())
+        assert_eq!(buffer.lines.as_ref().unwrap()[0], b"alpha")
     }
 
     #[rstest]
@@ -303,11 +619,11 @@ mod tests {
         // copy buffer to avoid clobbering original data
         let mut buffer = test_file1.clone();
         let address = Address::Last;
-        let lines = vec!["alpha".to_string()];
-        let actual = append_into_buffer(&mut buffer, &address, lines);
+        let lines = vec![b"alpha".to_vec()];
+        let actual = append_into_buffer(&mut buffer, &address, lines).unwrap();
         assert_eq!(actual, 6);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 6);
-        assert_eq!(buffer.lines.as_ref().unwrap()[5], "alpha".to_string())
+        assert_eq!(buffer.lines.as_ref().unwrap()[5], b"alpha")
     }
 
     #[rstest]
@@ -317,15 +633,35 @@ mod tests {
         let mut buffer = test_file1.clone();
         let address1 = Address::Absolute(2);
         let address2 = Address::Absolute(2);
-        let lines = vec!["alpha".to_string()];
+        let lines = vec![b"alpha".to_vec()];
         let actual = correct_into_buffer(&mut buffer, &address1, &address2, lines)
             .expect("Unable to change buffer.");
         assert_eq!(actual, 2);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 5);
-        assert_eq!(buffer.lines.as_ref().unwrap()[0], "one".to_string());
-        assert_eq!(buffer.lines.as_ref().unwrap()[1], "alpha".to_string());
-        assert_eq!(buffer.lines.as_ref().unwrap()[3], "four".to_string());
-        assert_eq!(buffer.lines.as_ref().unwrap()[4], "five".to_string());
+        assert_eq!(buffer.lines.as_ref().unwrap()[0], b"one");
+        assert_eq!(buffer.lines.as_ref().unwrap()[1], b"alpha");
+        assert_eq!(buffer.lines.as_ref().unwrap()[3], b"four");
+        assert_eq!(buffer.lines.as_ref().unwrap()[4], b"five");
+    }
+
+    #[rstest]
+    #[case("2,3c", &["alpha"], 2, vec!["one", "alpha", "four", "five"])]
+    #[case("2c", &["alpha", "beta", "gamma"], 4, vec!["one", "alpha", "beta", "gamma", "three", "four", "five"])]
+    #[case("1,4c", &["alpha", "beta"], 2, vec!["alpha", "beta", "five"])]
+    fn rstest_correct_current_line(
+        test_file1: &LineBuffer,
+        #[case] line: &str,
+        #[case] input: &[&str],
+        #[case] current: usize,
+        #[case] expected: Vec<&str>,
+    ) {
+        let mut buffer = test_file1.clone();
+        let mut input: VecDeque<Vec<u8>> =
+            input.iter().chain(&["."]).map(|s| s.as_bytes().to_vec()).collect();
+        let command = parse_line(line.as_bytes()).expect("bad line parse");
+        command_runner(&mut buffer, &command, &mut input).expect("change failed");
+        assert_eq!(buffer.lines.unwrap(), text_lines(&expected));
+        assert_eq!(buffer.current_line, current);
     }
 
     #[rstest]
@@ -338,10 +674,10 @@ mod tests {
             .expect("Unable to change buffer.");
         assert_eq!(actual, 1);
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 4);
-        assert_eq!(buffer.lines.as_ref().unwrap()[0], "one".to_string());
-        assert_eq!(buffer.lines.as_ref().unwrap()[1], "three".to_string());
-        assert_eq!(buffer.lines.as_ref().unwrap()[2], "four".to_string());
-        assert_eq!(buffer.lines.as_ref().unwrap()[3], "five".to_string());
+        assert_eq!(buffer.lines.as_ref().unwrap()[0], b"one");
+        assert_eq!(buffer.lines.as_ref().unwrap()[1], b"three");
+        assert_eq!(buffer.lines.as_ref().unwrap()[2], b"four");
+        assert_eq!(buffer.lines.as_ref().unwrap()[3], b"five");
     }
 
     #[rstest]
@@ -356,6 +692,151 @@ mod tests {
         assert_eq!(buffer.lines.as_ref().unwrap().len(), 0);
     }
 
+    #[rstest]
+    fn test_marks_follow_insert_and_delete(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        buffer.marks.insert('a', 2);
+        buffer.marks.insert('b', 4);
+        insert_into_buffer(&mut buffer, &Address::Absolute(1), vec![b"alpha".to_vec()]).unwrap();
+        assert_eq!(buffer.marks.get(&'a'), Some(&3));
+        assert_eq!(buffer.marks.get(&'b'), Some(&5));
+
+        delete_from_buffer(&mut buffer, &Address::Absolute(3), &Address::Absolute(3))
+            .expect("Unable to change buffer.");
+        assert_eq!(buffer.marks.get(&'a'), None);
+        assert_eq!(buffer.marks.get(&'b'), Some(&4));
+    }
+
+    #[rstest]
+    fn test_join_middle(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        buffer.marks.insert('a', 5);
+        let actual = join_into_buffer(&mut buffer, &Address::Absolute(2), &Address::Absolute(4))
+            .expect("Unable to change buffer.");
+        assert_eq!(actual, 2);
+        assert_eq!(buffer.lines.as_ref().unwrap().len(), 3);
+        assert_eq!(buffer.lines.as_ref().unwrap()[1], b"twothreefour");
+        assert_eq!(buffer.marks.get(&'a'), Some(&3));
+    }
+
+    #[rstest]
+    #[case("j", 3, vec!["one", "two", "threefour", "five"])]
+    #[case("2j", 3, vec!["one", "two", "three", "four", "five"])]
+    #[case("1,$jp", 1, vec!["onetwothreefourfive"])]
+    fn rstest_join_command(
+        test_file1: &LineBuffer,
+        #[case] line: &str,
+        #[case] current: usize,
+        #[case] expected: Vec<&str>,
+    ) {
+        let mut buffer = test_file1.clone();
+        buffer.current_line = 3;
+        run(&mut buffer, line).expect("join failed");
+        assert_eq!(buffer.lines.unwrap(), text_lines(&expected));
+        assert_eq!(buffer.current_line, current);
+    }
+
+    #[rstest]
+    #[case("j")]
+    #[case("1,2jx")]
+    fn rstest_join_errors(test_file1: &LineBuffer, #[case] line: &str) {
+        let mut buffer = test_file1.clone();
+        buffer.current_line = 5;
+        assert!(run(&mut buffer, line).is_err());
+    }
+
+    #[rstest]
+    #[case("2,3m$", 5, vec!["one", "four", "five", "two", "three"])]
+    #[case("4,5m0", 2, vec!["four", "five", "one", "two", "three"])]
+    #[case("2m3", 3, vec!["one", "three", "two", "four", "five"])]
+    #[case("2,3m3", 3, vec!["one", "two", "three", "four", "five"])]
+    #[case("1,2t$", 7, vec!["one", "two", "three", "four", "five", "one", "two"])]
+    #[case("2,3t0", 2, vec!["two", "three", "one", "two", "three", "four", "five"])]
+    #[case("2,4t3", 6, vec!["one", "two", "three", "two", "three", "four", "four", "five"])]
+    fn rstest_move_and_transfer(
+        test_file1: &LineBuffer,
+        #[case] line: &str,
+        #[case] current: usize,
+        #[case] expected: Vec<&str>,
+    ) {
+        let mut buffer = test_file1.clone();
+        run(&mut buffer, line).expect("command failed");
+        assert_eq!(buffer.lines.unwrap(), text_lines(&expected));
+        assert_eq!(buffer.current_line, current);
+    }
+
+    #[rstest]
+    fn test_move_keeps_marks(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        buffer.marks.insert('a', 2);
+        buffer.marks.insert('b', 5);
+        move_into_buffer(
+            &mut buffer,
+            &Address::Absolute(1),
+            &Address::Absolute(2),
+            &Address::Absolute(4),
+        )
+        .expect("Unable to change buffer.");
+        assert_eq!(buffer.marks.get(&'a'), Some(&4));
+        assert_eq!(buffer.marks.get(&'b'), Some(&5));
+    }
+
+    #[rstest]
+    fn test_move_into_itself_returns_err(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        let result = move_into_buffer(
+            &mut buffer,
+            &Address::Absolute(2),
+            &Address::Absolute(4),
+            &Address::Absolute(3),
+        );
+        assert!(matches!(result, Err(EdCommandError::InvalidDestination)));
+        assert_eq!(buffer.lines, test_file1.lines);
+    }
+
+    #[rstest]
+    #[case(&["2,3d", "$x"], 5, vec!["one", "four", "five", "two", "three"])]
+    #[case(&["4,5y", "0x"], 2, vec!["four", "five", "one", "two", "three", "four", "five"])]
+    #[case(&["1,2j", "2x"], 4, vec!["onetwo", "three", "one", "two", "four", "five"])]
+    #[case(&["5d", "x", "x"], 6, vec!["one", "two", "three", "four", "five", "five"])]
+    fn rstest_cut_buffer(
+        test_file1: &LineBuffer,
+        #[case] lines: &[&str],
+        #[case] current: usize,
+        #[case] expected: Vec<&str>,
+    ) {
+        let mut buffer = test_file1.clone();
+        for line in lines {
+            run(&mut buffer, line).expect("command failed");
+        }
+        assert_eq!(buffer.lines.unwrap(), text_lines(&expected));
+        assert_eq!(buffer.current_line, current);
+    }
+
+    #[rstest]
+    fn test_put_empty_cut_buffer_returns_err(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        let result = put(&mut buffer, &EdCommand::default());
+        assert!(matches!(result, Err(EdCommandError::NothingToPut)));
+    }
+
+    #[rstest]
+    fn test_undo_restores_global_command(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        for line in ["g/e/s/e/E/\\\na\\\nnew", "u"] {
+            run(&mut buffer, line).expect("command failed");
+        }
+        assert_eq!(buffer.lines, test_file1.lines);
+        assert_eq!(buffer.current_line, test_file1.current_line);
+    }
+
+    #[rstest]
+    fn test_undo_with_no_changes_returns_err(test_file1: &LineBuffer) {
+        let mut buffer = test_file1.clone();
+        let result = undo(&mut buffer, &EdCommand::default());
+        assert!(matches!(result, Err(EdCommandError::NothingToUndo)));
+    }
+
     #[rstest]
     fn test_delete_zero_returns_err(test_file1: &LineBuffer) {
         // copy buffer to avoid clobbering original data
@@ -366,9 +847,6 @@ mod tests {
         };
         let result = delete(&mut buffer, &command);
 
-        match result {
-            Err(EdCommandError::InvalidRange) => assert!(true),
-            _ => assert!(false),
-        };
+        assert!(matches!(result, Err(EdCommandError::InvalidRange)));
     }
 }
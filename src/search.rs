@@ -0,0 +1,284 @@
+//! This module handles regular expression searches through the buffer.
+//!
+//! * LineRegex: A compiled pattern that matches lines that are not UTF-8 byte by byte.
+//! * pattern_regex: Compile a pattern, falling back to the last pattern used.
+//! * search_forward: Find the next matching line, wrapping to the top.
+//! * search_backward: Find the previous matching line, wrapping to the bottom.
+//! * split_delimiter: Split the delimiter from the start of a command's arguments.
+//! * read_delimited: Read bytes up to a closing delimiter.
+//! * read_pattern: Read a regular expression up to its closing delimiter.
+
+use crate::buffer::line_array_buffer::LineBuffer;
+use crate::ed_command_error::EdCommandError;
+use regex::bytes::{Regex, RegexBuilder};
+
+/// A compiled search pattern.
+///
+/// Lines that are UTF-8 are matched by character, so `.` matches all of
+/// `é`. Other lines, such as Latin-1 text, are matched with Unicode mode
+/// off, so `.` and `[^...]` match any single byte.
+#[derive(Debug, Clone)]
+pub struct LineRegex {
+    unicode: Regex,
+    bytes: Regex,
+}
+
+impl LineRegex {
+    /// Returns the expression that `line` is matched with.
+    pub fn for_line(&self, line: &[u8]) -> &Regex {
+        if std::str::from_utf8(line).is_ok() {
+            &self.unicode
+        } else {
+            &self.bytes
+        }
+    }
+
+    /// Returns `true` if the pattern matches somewhere in `line`.
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        self.for_line(line).is_match(line)
+    }
+}
+
+/// Compiles a search pattern into a `LineRegex` that matches the bytes of a line.
+///
+/// An empty pattern reuses `buffer.last_pattern`, as with `//` and `??`.
+/// Bytes of the pattern that are not UTF-8 are matched by turning off
+/// Unicode mode for them, as in `(?-u:\xe9)`. For lines that are not UTF-8,
+/// every non-ASCII byte of the pattern is matched as a byte; a pattern that
+/// needs Unicode mode, such as `\p{L}`, matches them by character instead.
+///
+/// # Arguments
+///
+/// * `pattern` - The bytes of the regular expression from the command.
+/// * `buffer` - A reference to the `LineBuffer` holding the last pattern.
+///
+/// # Returns
+///
+/// * `Result<LineRegex, EdCommandError>` - The compiled expression, `EdCommandError::NoPreviousPattern` if
+///   the pattern is empty and no search has been made yet, or `EdCommandError::InvalidPattern` if it
+///   does not compile.
+pub fn pattern_regex(pattern: &[u8], buffer: &LineBuffer) -> Result<LineRegex, EdCommandError> {
+    let pattern = if pattern.is_empty() {
+        buffer
+            .last_pattern
+            .as_deref()
+            .ok_or(EdCommandError::NoPreviousPattern)?
+    } else {
+        pattern
+    };
+    let mut text = String::new();
+    for chunk in pattern.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            text.push_str(&format!("(?-u:\\x{:02x})", byte));
+        }
+    }
+    let unicode = Regex::new(&text).map_err(EdCommandError::InvalidPattern)?;
+
+    let text: String = pattern
+        .iter()
+        .map(|&byte| match byte {
+            0x80.. => format!("\\x{:02x}", byte),
+            _ => (byte as char).to_string(),
+        })
+        .collect();
+    let bytes = RegexBuilder::new(&text)
+        .unicode(false)
+        .build()
+        .unwrap_or_else(|_| unicode.clone());
+    Ok(LineRegex { unicode, bytes })
+}
+
+/// Splits the delimiter character from the start of `args`.
+///
+/// # Returns
+///
+/// The delimiter and the bytes after it, or `None` if `args` is empty or
+/// does not start with a UTF-8 character.
+pub fn split_delimiter(args: &[u8]) -> Option<(char, &[u8])> {
+    let delimiter = args.utf8_chunks().next()?.valid().chars().next()?;
+    Some((delimiter, &args[delimiter.len_utf8()..]))
+}
+
+/// Reads bytes from the start of `text` up to an unescaped `delimiter`,
+/// leaving `text` at the byte after it.
+///
+/// Escape sequences are kept as written, except that `\` followed by the
+/// delimiter becomes the bare delimiter when `unescape_delimiter` is set.
+///
+/// # Returns
+///
+/// A tuple of the bytes read and whether the closing delimiter was found.
+pub fn read_delimited(
+    text: &mut &[u8],
+    delimiter: char,
+    unescape_delimiter: bool,
+) -> (Vec<u8>, bool) {
+    let mut encoded = [0; 4];
+    let delimiter = delimiter.encode_utf8(&mut encoded).as_bytes();
+    let mut read = vec![];
+    while let Some((&byte, rest)) = text.split_first() {
+        if text.starts_with(delimiter) {
+            *text = &text[delimiter.len()..];
+            return (read, true);
+        }
+        *text = rest;
+        if byte != b'\\' {
+            read.push(byte);
+        } else if unescape_delimiter && text.starts_with(delimiter) {
+            read.extend_from_slice(delimiter);
+            *text = &text[delimiter.len()..];
+        } else {
+            read.push(byte);
+            if let Some((&next, rest)) = text.split_first() {
+                read.push(next);
+                *text = rest;
+            }
+        }
+    }
+    (read, false)
+}
+
+/// Reads a regular expression from the start of `text` up to an unescaped
+/// `delimiter`, leaving `text` at the byte after it. Used for `/re/`,
+/// `?re?`, `s` and `g` so that all patterns are read the same way.
+///
+/// An escaped delimiter matches the delimiter itself: it becomes the bare
+/// delimiter unless that is special to the regex engine, as `?` is, in which
+/// case the escape is kept.
+///
+/// # Returns
+///
+/// A tuple of the pattern and whether the closing delimiter was found.
+pub fn read_pattern(text: &mut &[u8], delimiter: char) -> (Vec<u8>, bool) {
+    let unescape = !regex::escape(&delimiter.to_string()).starts_with('\\');
+    read_delimited(text, delimiter, unescape)
+}
+
+/// Remembers a non-empty pattern as the last pattern so that later empty
+/// patterns can reuse it.
+pub fn remember_pattern(pattern: &[u8], buffer: &mut LineBuffer) {
+    if !pattern.is_empty() {
+        buffer.last_pattern = Some(pattern.to_vec());
+    }
+}
+
+/// Searches forward from the line after `buffer.current_line` for a line
+/// matching `pattern`. The search wraps to the first line and ends on the
+/// current line.
+///
+/// # Returns
+///
+/// * `Result<usize, EdCommandError>` - The one-indexed line number of the match, or `EdCommandError::NoMatch`.
+pub fn search_forward(pattern: &[u8], buffer: &LineBuffer) -> Result<usize, EdCommandError> {
+    let re = pattern_regex(pattern, buffer)?;
+    let len = buffer.len();
+    let lines = buffer.lines.as_deref().unwrap_or_default();
+    (1..=len)
+        .map(|step| (buffer.current_line + step - 1) % len + 1)
+        .find(|&line| re.is_match(&lines[line - 1]))
+        .ok_or(EdCommandError::NoMatch)
+}
+
+/// Searches backward from the line before `buffer.current_line` for a line
+/// matching `pattern`. The search wraps to the last line and ends on the
+/// current line.
+///
+/// # Returns
+///
+/// * `Result<usize, EdCommandError>` - The one-indexed line number of the match, or `EdCommandError::NoMatch`.
+pub fn search_backward(pattern: &[u8], buffer: &LineBuffer) -> Result<usize, EdCommandError> {
+    let re = pattern_regex(pattern, buffer)?;
+    let len = buffer.len();
+    let lines = buffer.lines.as_deref().unwrap_or_default();
+    // line 0 sits before line 1, so searching back from it starts at `$`
+    let current = buffer.current_line.max(1) as isize;
+    (1..=len)
+        .map(|step| (current - step as isize - 1).rem_euclid(len as isize) as usize + 1)
+        .find(|&line| re.is_match(&lines[line - 1]))
+        .ok_or(EdCommandError::NoMatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn test_buffer(current_line: usize) -> LineBuffer {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        buffer.current_line = current_line;
+        buffer
+    }
+
+    #[rstest]
+    #[case("f", 1, 4)]
+    #[case("f", 4, 5)]
+    #[case("f", 5, 4)]
+    #[case("one", 1, 1)]
+    #[case("^t", 3, 2)]
+    fn rstest_search_forward(#[case] pattern: &str, #[case] current: usize, #[case] expected: usize) {
+        let buffer = test_buffer(current);
+        assert_eq!(search_forward(pattern.as_bytes(), &buffer).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("f", 5, 4)]
+    #[case("f", 4, 5)]
+    #[case("t", 2, 3)]
+    #[case("one", 1, 1)]
+    fn rstest_search_backward(#[case] pattern: &str, #[case] current: usize, #[case] expected: usize) {
+        let buffer = test_buffer(current);
+        assert_eq!(search_backward(pattern.as_bytes(), &buffer).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_search_no_match() {
+        let buffer = test_buffer(1);
+        assert!(matches!(
+            search_forward(b"zzz", &buffer),
+            Err(EdCommandError::NoMatch)
+        ));
+    }
+
+    #[rstest]
+    #[case("caf.$", 2, 1)]
+    #[case("f[^a]$", 2, 1)]
+    #[case("(?-u:\\xe9)", 2, 1)]
+    #[case("caf.$", 1, 2)]
+    #[case("\u{e9}", 2, 2)]
+    fn rstest_search_latin1(#[case] pattern: &str, #[case] current: usize, #[case] expected: usize) {
+        let mut buffer = LineBuffer::empty();
+        buffer.lines = Some(vec![b"caf\xe9".to_vec(), "caf\u{e9}".as_bytes().to_vec()]);
+        buffer.current_line = current;
+        assert_eq!(search_forward(pattern.as_bytes(), &buffer).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("a\\/b/p", '/', "a/b", true, "p")]
+    #[case("a\\?b?p", '?', "a\\?b", true, "p")]
+    #[case("a\\.b|", '|', "a\\.b", true, "")]
+    #[case("a\\|b", '|', "a\\|b", false, "")]
+    fn rstest_read_pattern(
+        #[case] text: &str,
+        #[case] delimiter: char,
+        #[case] pattern: &str,
+        #[case] terminated: bool,
+        #[case] rest: &str,
+    ) {
+        let mut text = text.as_bytes();
+        assert_eq!(read_pattern(&mut text, delimiter), (pattern.as_bytes().to_vec(), terminated));
+        assert_eq!(text, rest.as_bytes());
+    }
+
+    #[test]
+    fn test_empty_pattern_reuses_last() {
+        let mut buffer = test_buffer(1);
+        assert!(matches!(
+            search_forward(b"", &buffer),
+            Err(EdCommandError::NoPreviousPattern)
+        ));
+        remember_pattern(b"ve$", &mut buffer);
+        assert_eq!(search_forward(b"", &buffer).unwrap(), 5);
+    }
+}
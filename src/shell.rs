@@ -0,0 +1,216 @@
+//! This module runs shell commands for `e !command` and related commands.
+//!
+//! * command_output: Run a command and collect its standard output as lines.
+//! * command_input: Run a command with lines of text as its standard input.
+//! * filter_lines: Run a command with lines as its input and collect its output.
+//! * run_shell: Run a command on the terminal.
+//! * expand_command: Expand `!!` and `%` in the text of a `!` command.
+
+use crate::buffer::line_array_buffer::split_lines;
+use crate::ed_command_error::EdCommandError;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `command` with `sh -c` and returns its standard output split into
+/// lines of bytes. Standard error is passed through to the terminal.
+///
+/// # Arguments
+///
+/// * `command` - The shell command line to run.
+///
+/// # Returns
+///
+/// * `Result<Vec<Vec<u8>>, std::io::Error>` - The lines written by the command, or an error if it could not be started.
+pub fn command_output(command: &str) -> Result<Vec<Vec<u8>>, std::io::Error> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    Ok(split_lines(&output.stdout, false).lines)
+}
+
+/// Runs `command` with `sh -c`, writing `lines` to its standard input.
+/// Its output is passed through to the terminal.
+///
+/// # Arguments
+///
+/// * `command` - The shell command line to run.
+/// * `lines` - The lines to write to the command, each followed by a newline.
+///
+/// # Returns
+///
+/// * `Result<(), std::io::Error>` - An error if the command could not be started or written to.
+pub fn command_input(command: &str, lines: &[Vec<u8>]) -> Result<(), std::io::Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        for line in lines {
+            // a command that stops reading early is not an error
+            if write_line(&mut stdin, line).is_err() {
+                break;
+            }
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Runs `command` with `sh -c`, writing `lines` to its standard input and
+/// returning its standard output split into lines of bytes. The input is written
+/// from another thread so a command that produces output as it reads cannot
+/// block.
+///
+/// # Arguments
+///
+/// * `command` - The shell command line to run.
+/// * `lines` - The lines to write to the command, each followed by a newline.
+///
+/// # Returns
+///
+/// * `Result<Vec<Vec<u8>>, std::io::Error>` - The lines written by the command, or an error if it could not be started.
+pub fn filter_lines(command: &str, lines: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>, std::io::Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        std::thread::spawn(move || {
+            for line in lines {
+                if write_line(&mut stdin, &line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    let output = child.wait_with_output()?;
+    Ok(split_lines(&output.stdout, false).lines)
+}
+
+/// Writes one line of bytes followed by a newline.
+fn write_line(writer: &mut impl Write, line: &[u8]) -> Result<(), std::io::Error> {
+    writer.write_all(line)?;
+    writer.write_all(b"\n")
+}
+
+/// Runs `command` with `sh -c` on the terminal, waiting for it to finish.
+///
+/// # Arguments
+///
+/// * `command` - The shell command line to run.
+///
+/// # Returns
+///
+/// * `Result<(), std::io::Error>` - An error if the command could not be started.
+pub fn run_shell(command: &str) -> Result<(), std::io::Error> {
+    Command::new("sh").arg("-c").arg(command).status()?;
+    Ok(())
+}
+
+/// Expands the text of a `!` command. A leading `!` is replaced by the
+/// previous command, and `%` by the default filename; `\%` stands for a
+/// literal `%`.
+///
+/// # Arguments
+///
+/// * `text` - The command text after the `!`.
+/// * `previous` - The last command that was run, if any.
+/// * `filename` - The default filename, if any.
+///
+/// # Returns
+///
+/// * `Result<(String, bool), EdCommandError>` - The expanded command and whether anything was expanded,
+///   `EdCommandError::NoPreviousCommand` for `!!` with no earlier command, or
+///   `EdCommandError::NoCurrentFilename` for `%` with no default filename.
+pub fn expand_command(
+    text: &str,
+    previous: Option<&str>,
+    filename: Option<&str>,
+) -> Result<(String, bool), EdCommandError> {
+    let mut expanded = String::new();
+    let mut changed = false;
+    let mut chars = text.chars().peekable();
+
+    if chars.peek() == Some(&'!') {
+        chars.next();
+        expanded.push_str(previous.ok_or(EdCommandError::NoPreviousCommand)?);
+        changed = true;
+    }
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'%') => {
+                expanded.push('%');
+                chars.next();
+            }
+            '%' => {
+                expanded.push_str(filename.ok_or(EdCommandError::NoCurrentFilename)?);
+                changed = true;
+            }
+            _ => expanded.push(c),
+        }
+    }
+    Ok((expanded, changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::line_array_buffer::text_lines;
+    use rstest::rstest;
+
+    #[test]
+    fn test_command_output() {
+        let lines = command_output("printf 'one\\ntwo\\n'").unwrap();
+        assert_eq!(lines, text_lines(&["one", "two"]));
+    }
+
+    #[test]
+    fn test_command_input() {
+        let out_filename = "/tmp/command_input.txt";
+        let lines = text_lines(&["one", "two"]);
+        command_input(&format!("cat > {}", out_filename), &lines).unwrap();
+        assert_eq!(std::fs::read_to_string(out_filename).unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn test_filter_lines() {
+        let lines = text_lines(&["b", "a"]);
+        assert_eq!(filter_lines("sort", lines).unwrap(), text_lines(&["a", "b"]));
+    }
+
+    #[test]
+    fn test_filter_lines_keeps_bytes() {
+        let lines = vec![b"caf\xe9\0".to_vec()];
+        assert_eq!(filter_lines("cat", lines.clone()).unwrap(), lines);
+    }
+
+    #[rstest]
+    #[case("ls", "ls", false)]
+    #[case("wc %", "wc file.txt", true)]
+    #[case("echo 100\\%", "echo 100%", false)]
+    #[case("!", "make", true)]
+    #[case("! test", "make test", true)]
+    #[case("a\\b", "a\\b", false)]
+    fn rstest_expand_command(#[case] text: &str, #[case] expected: &str, #[case] changed: bool) {
+        let result = expand_command(text, Some("make"), Some("file.txt")).unwrap();
+        assert_eq!(result, (expected.to_string(), changed));
+    }
+
+    #[test]
+    fn test_expand_command_errors() {
+        assert!(matches!(
+            expand_command("!", None, None),
+            Err(EdCommandError::NoPreviousCommand)
+        ));
+        assert!(matches!(
+            expand_command("cat %", None, None),
+            Err(EdCommandError::NoCurrentFilename)
+        ));
+    }
+}
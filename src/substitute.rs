@@ -0,0 +1,382 @@
+//! This module handles the substitute command.
+//!
+//! * parse_substitution: Split `s` arguments into pattern, replacement and flags.
+//! * substitute_line: Apply a substitution to the text of one line.
+//! * substitute: Run `(.,.)s/re/replacement/flags` over a range.
+
+use crate::buffer::line_array_buffer::LineBuffer;
+use crate::command_structs::{Address, EdCommand, PrintSuffix, Substitution};
+use crate::ed_command_error::EdCommandError;
+use crate::ed_commands::*;
+use crate::search::{pattern_regex, read_delimited, read_pattern, remember_pattern, split_delimiter};
+use regex::bytes::{Captures, Regex};
+
+/// Parses substitute flags: `g`, a count `N`, and the `p`, `l` or `n` suffix.
+fn parse_flags(flags: &[u8], substitution: &mut Substitution) -> Result<(), EdCommandError> {
+    let mut count = String::new();
+    for &byte in flags {
+        match byte {
+            b'g' => substitution.global = true,
+            b'p' => substitution.suffix = Some(PrintSuffix::Print),
+            b'l' => substitution.suffix = Some(PrintSuffix::List),
+            b'n' => substitution.suffix = Some(PrintSuffix::Number),
+            b'0'..=b'9' => count.push(byte as char),
+            _ => return Err(EdCommandError::InvalidCommandSuffix),
+        }
+    }
+    if !count.is_empty() {
+        substitution.occurrence = match count.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(EdCommandError::InvalidCommandSuffix),
+        };
+    }
+    Ok(())
+}
+
+/// Parses the arguments of an `s` command.
+///
+/// A missing argument, or one made only of flags, repeats the last
+/// substitution. A replacement of `%` reuses the last replacement, and an
+/// empty pattern reuses the last pattern. When the closing delimiter of the
+/// replacement is omitted the result is printed, as if `p` had been given.
+///
+/// # Arguments
+///
+/// * `args` - The bytes after the `s` command, if any.
+/// * `buffer` - A reference to the `LineBuffer` holding the last substitution and pattern.
+///
+/// # Returns
+///
+/// * `Result<Substitution, EdCommandError>` - The parsed substitution, or an error if the arguments are malformed.
+pub fn parse_substitution(
+    args: Option<&[u8]>,
+    buffer: &LineBuffer,
+) -> Result<Substitution, EdCommandError> {
+    let args = args.unwrap_or_default();
+    let (delimiter, mut rest) = match split_delimiter(args) {
+        Some((c, rest)) if !c.is_ascii_alphanumeric() => (c, rest),
+        _ => {
+            let mut substitution = buffer
+                .last_substitution
+                .clone()
+                .ok_or(EdCommandError::NoPreviousSubstitution)?;
+            if !args.is_empty() {
+                substitution.global = false;
+                substitution.occurrence = 1;
+                substitution.suffix = None;
+                parse_flags(args, &mut substitution)?;
+            }
+            return Ok(substitution);
+        }
+    };
+
+    if delimiter.is_whitespace() || delimiter == '\\' {
+        return Err(EdCommandError::InvalidPatternDelimiter);
+    }
+
+    let (pattern, terminated) = read_pattern(&mut rest, delimiter);
+    if !terminated {
+        return Err(EdCommandError::MissingPatternDelimiter);
+    }
+    let (mut replacement, terminated) = read_delimited(&mut rest, delimiter, false);
+
+    let pattern = if pattern.is_empty() {
+        buffer
+            .last_pattern
+            .clone()
+            .ok_or(EdCommandError::NoPreviousPattern)?
+    } else {
+        pattern
+    };
+
+    if replacement == b"%" {
+        replacement = buffer
+            .last_substitution
+            .as_ref()
+            .map(|s| s.replacement.clone())
+            .ok_or(EdCommandError::NoPreviousSubstitution)?;
+    }
+
+    let mut substitution = Substitution {
+        pattern,
+        replacement,
+        global: false,
+        occurrence: 1,
+        suffix: None,
+    };
+
+    if terminated {
+        parse_flags(rest, &mut substitution)?;
+    } else {
+        substitution.suffix = Some(PrintSuffix::Print);
+    }
+    Ok(substitution)
+}
+
+/// Expands `&`, `\1`-`\9` and backslash escapes in `replacement` for one
+/// match, appending the result to `out`. An escaped newline is kept as a
+/// newline, which later splits the line. Other bytes, UTF-8 or not, are
+/// copied unchanged.
+fn expand_replacement(captures: &Captures, replacement: &[u8], out: &mut Vec<u8>) {
+    let mut bytes = replacement.iter().copied();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'&' => out.extend_from_slice(&captures[0]),
+            b'\\' => match bytes.next() {
+                Some(d @ b'1'..=b'9') => {
+                    let group = (d - b'0') as usize;
+                    out.extend_from_slice(captures.get(group).map_or(&[][..], |m| m.as_bytes()));
+                }
+                Some(other) => out.push(other),
+                None => out.push(b'\\'),
+            },
+            _ => out.push(byte),
+        }
+    }
+}
+
+/// Applies a substitution to the bytes of one line.
+///
+/// # Returns
+///
+/// * `Option<Vec<u8>>` - The new text, which may contain newlines, or `None` if nothing was replaced.
+pub fn substitute_line(re: &Regex, line: &[u8], substitution: &Substitution) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut last_end = 0;
+    let mut replaced = false;
+
+    for (count, captures) in re.captures_iter(line).enumerate() {
+        if count + 1 < substitution.occurrence {
+            continue;
+        }
+        let whole = captures.get(0)?;
+        result.extend_from_slice(&line[last_end..whole.start()]);
+        expand_replacement(&captures, &substitution.replacement, &mut result);
+        last_end = whole.end();
+        replaced = true;
+        if !substitution.global {
+            break;
+        }
+    }
+
+    if !replaced {
+        return None;
+    }
+    result.extend_from_slice(&line[last_end..]);
+    Some(result)
+}
+
+/// Replaces text matching a regular expression in the addressed lines.
+///
+/// Sets `buffer.current_line` to the last line changed and saves the
+/// substitution so a bare `s` can repeat it.
+///
+/// # Arguments
+///
+/// * `buffer` - A mutable reference to the `LineBuffer` being edited.
+/// * `command` - A reference to the `EdCommand` containing the range and the `s` arguments.
+///
+/// # Returns
+///
+/// Returns `Ok(REPLStatus::Continue)` if at least one line was changed, or
+/// `EdCommandError::NoMatch` if nothing in the range matched.
+pub fn substitute(buffer: &mut LineBuffer, command: &EdCommand) -> Result<REPLStatus, EdCommandError> {
+    if command.address1 == Address::Absolute(0) || buffer.len() == 0 {
+        return Err(EdCommandError::InvalidRange);
+    }
+
+    let substitution = parse_substitution(command.command_args.as_deref(), buffer)?;
+    let re = pattern_regex(&substitution.pattern, buffer)?;
+    remember_pattern(&substitution.pattern, buffer);
+    buffer.last_substitution = Some(substitution.clone());
+
+    let mut index = address_to_index(command.address1.clone(), buffer)?;
+    let high = address_to_index(command.address2.clone(), buffer)?;
+    let mut remaining = high - index + 1;
+    let mut last_changed = None;
+
+    while remaining > 0 {
+        let line = buffer.lines.as_ref().map_or(&[][..], |lines| lines[index].as_slice());
+        match substitute_line(re.for_line(line), line, &substitution) {
+            Some(new_text) if new_text.contains(&b'\n') => {
+                let new_lines: Vec<Vec<u8>> =
+                    new_text.split(|&byte| byte == b'\n').map(<[u8]>::to_vec).collect();
+                let added = new_lines.len();
+                buffer.splice_lines(index, index + 1, new_lines);
+                index += added;
+                last_changed = Some(index);
+            }
+            Some(new_text) => {
+                buffer.set_line(index, new_text);
+                index += 1;
+                last_changed = Some(index);
+            }
+            None => index += 1,
+        }
+        remaining -= 1;
+    }
+
+    buffer.current_line = last_changed.ok_or(EdCommandError::NoMatch)?;
+    print_suffix(buffer, substitution.suffix);
+    Ok(REPLStatus::Continue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn parse(args: &str) -> Substitution {
+        parse_substitution(Some(args.as_bytes()), &LineBuffer::empty()).expect("bad substitution")
+    }
+
+    #[rstest]
+    #[case("/o/0/", "one two", "0ne two")]
+    #[case("/o/0/g", "one two", "0ne tw0")]
+    #[case("/o/0/2", "one two", "one tw0")]
+    #[case("|t(w)o|<&:\\1>|", "one two", "one <two:w>")]
+    #[case("/o/\\&/", "one", "&ne")]
+    #[case("/ /\\\n/", "one two", "one\ntwo")]
+    #[case(",e,\\,,g", "one three", "on, thr,,")]
+    fn rstest_substitute_line(#[case] args: &str, #[case] line: &str, #[case] expected: &str) {
+        let substitution = parse(args);
+        let re = Regex::new(std::str::from_utf8(&substitution.pattern).unwrap()).unwrap();
+        assert_eq!(
+            substitute_line(&re, line.as_bytes(), &substitution).as_deref(),
+            Some(expected.as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_substitute_line_no_match() {
+        let substitution = parse("/z/y/");
+        let re = Regex::new(std::str::from_utf8(&substitution.pattern).unwrap()).unwrap();
+        assert_eq!(substitute_line(&re, b"one", &substitution), None);
+    }
+
+    #[test]
+    fn test_substitute_line_keeps_other_bytes() {
+        let substitution = parse("/(?-u:\\xe9)/e/");
+        let re = Regex::new(std::str::from_utf8(&substitution.pattern).unwrap()).unwrap();
+        assert_eq!(
+            substitute_line(&re, b"caf\xe9 \xff\0", &substitution).as_deref(),
+            Some(&b"cafe \xff\0"[..])
+        );
+    }
+
+    #[rstest]
+    #[case("/a/b/gp", true, 1, Some(PrintSuffix::Print))]
+    #[case("/a/b/3n", false, 3, Some(PrintSuffix::Number))]
+    #[case("/a/b", false, 1, Some(PrintSuffix::Print))]
+    #[case("/a/b/", false, 1, None)]
+    fn rstest_parse_flags(
+        #[case] args: &str,
+        #[case] global: bool,
+        #[case] occurrence: usize,
+        #[case] suffix: Option<PrintSuffix>,
+    ) {
+        let substitution = parse(args);
+        assert_eq!(substitution.global, global);
+        assert_eq!(substitution.occurrence, occurrence);
+        assert_eq!(substitution.suffix, suffix);
+    }
+
+    #[rstest]
+    #[case("/a")]
+    #[case(" a b ")]
+    #[case("/a/b/x")]
+    #[case("/a/b/0")]
+    fn rstest_parse_errors(#[case] args: &str) {
+        assert!(parse_substitution(Some(args.as_bytes()), &LineBuffer::empty()).is_err());
+    }
+
+    #[test]
+    fn test_repeat_and_percent_replacement() {
+        let mut buffer = LineBuffer::empty();
+        assert!(matches!(
+            parse_substitution(None, &buffer),
+            Err(EdCommandError::NoPreviousSubstitution)
+        ));
+        buffer.last_substitution = Some(parse("/a/b/g"));
+        assert_eq!(parse_substitution(None, &buffer).unwrap(), parse("/a/b/g"));
+        assert_eq!(parse_substitution(Some(b"p"), &buffer).unwrap(), parse("/a/b/p"));
+        assert_eq!(
+            parse_substitution(Some(b"/c/%/"), &buffer).unwrap().replacement,
+            b"b"
+        );
+    }
+
+    #[test]
+    fn test_substitute_range() {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        let command = EdCommand {
+            address1: Address::Absolute(1),
+            address2: Address::Absolute(3),
+            command: Some("s".to_string()),
+            command_args: Some(b"/e/E/".to_vec()),
+            ..EdCommand::default()
+        };
+        substitute(&mut buffer, &command).expect("substitute failed");
+        let lines = buffer.lines.as_ref().unwrap();
+        assert_eq!(lines[0], b"onE");
+        assert_eq!(lines[1], b"two");
+        assert_eq!(lines[2], b"thrEe");
+        assert_eq!(buffer.current_line, 3);
+    }
+
+    #[test]
+    fn test_substitute_split_line() {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        buffer.marks.insert('a', 5);
+        let command = EdCommand {
+            address1: Address::Absolute(2),
+            address2: Address::Absolute(3),
+            command: Some("s".to_string()),
+            command_args: Some(b"/w|h/\\\n/".to_vec()),
+            ..EdCommand::default()
+        };
+        substitute(&mut buffer, &command).expect("substitute failed");
+        let lines = buffer.lines.as_ref().unwrap();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1], b"t");
+        assert_eq!(lines[2], b"o");
+        assert_eq!(lines[3], b"t");
+        assert_eq!(lines[4], b"ree");
+        assert_eq!(buffer.current_line, 5);
+        assert_eq!(buffer.marks.get(&'a'), Some(&7));
+    }
+
+    #[test]
+    fn test_substitute_latin1_line() {
+        let mut buffer = LineBuffer::empty();
+        buffer.lines = Some(vec![b"caf\xe9!".to_vec(), "caf\u{e9}!".as_bytes().to_vec()]);
+        let command = EdCommand {
+            address1: Address::Absolute(1),
+            address2: Address::Absolute(2),
+            command: Some("s".to_string()),
+            command_args: Some(b"/caf./X/".to_vec()),
+            ..EdCommand::default()
+        };
+        substitute(&mut buffer, &command).expect("substitute failed");
+        assert_eq!(buffer.lines.unwrap(), vec![b"X!".to_vec(), b"X!".to_vec()]);
+    }
+
+    #[test]
+    fn test_substitute_no_match() {
+        let filename = "test_files/one.txt";
+        let mut buffer = LineBuffer::from_file(filename).unwrap();
+        let command = EdCommand {
+            address1: Address::Absolute(1),
+            address2: Address::Last,
+            command: Some("s".to_string()),
+            command_args: Some(b"/zzz/y/".to_vec()),
+            ..EdCommand::default()
+        };
+        assert!(matches!(
+            substitute(&mut buffer, &command),
+            Err(EdCommandError::NoMatch)
+        ));
+    }
+}